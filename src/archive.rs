@@ -0,0 +1,211 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Cursor,
+    path::Path,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use bevy::prelude::*;
+use binrw::{BinRead, BinReaderExt};
+
+/// Size of a sector in an IMG archive, offsets and sizes in the .dir file are in sectors
+pub const SECTOR_SIZE: u64 = 2048;
+
+/// An entry in the directory (.dir) file belonging to a GTA III IMG archive
+#[derive(BinRead, Debug, Clone)]
+#[br(little)]
+pub struct DirEntry {
+    pub offset: u32,
+    pub size: u32,
+    #[br(map = |name: [u8; 24]| {
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..len]).into_owned()
+    })]
+    pub name: String,
+}
+
+// Reads all entries from the .dir file belonging to an IMG archive
+pub fn read_dir_entries(path: &Path) -> std::io::Result<Vec<DirEntry>> {
+    let dir = std::fs::read(path)?;
+    let num_entries = dir.len() / 32;
+    let mut cursor = Cursor::new(dir);
+    let mut entries = Vec::with_capacity(num_entries);
+    for _ in 0..num_entries {
+        let entry: DirEntry = cursor
+            .read_le()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// Writes the entries as a .dir file, names are padded with NULs to 24 bytes
+pub fn write_dir_entries(path: &Path, entries: &[DirEntry]) -> std::io::Result<()> {
+    let mut dir = Vec::with_capacity(entries.len() * 32);
+    for entry in entries {
+        let mut name = [0; 24];
+        let Some(slot) = name.get_mut(..entry.name.len()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("entry name {:?} is longer than 24 bytes", entry.name),
+            ));
+        };
+        slot.copy_from_slice(entry.name.as_bytes());
+        dir.extend_from_slice(&entry.offset.to_le_bytes());
+        dir.extend_from_slice(&entry.size.to_le_bytes());
+        dir.extend_from_slice(&name);
+    }
+    std::fs::write(path, dir)
+}
+
+/// An open IMG archive. Entries are read with positional reads, so reading only needs a shared
+/// reference and any number of threads can read from the same archive at once.
+pub struct ImgArchive {
+    file: File,
+    /// Length of the .img file, the last entry may not be padded to a full sector
+    len: u64,
+    pub entries: Vec<DirEntry>,
+    /// Lowercase name -> index into entries
+    index: HashMap<String, usize>,
+}
+
+impl ImgArchive {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).map_err(|e| format!("error opening {}: {e}", path.display()))?;
+        let len = file.metadata()?.len();
+        let entries = read_dir_entries(&path.with_extension("dir"))?;
+        // Later entries win, like they do in the game
+        let index = entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.name.to_ascii_lowercase(), i))
+            .collect();
+        Ok(Self {
+            file,
+            len,
+            entries,
+            index,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(&name.to_ascii_lowercase())
+    }
+
+    pub fn get_file(&self, name: &str) -> Option<Vec<u8>> {
+        let entry = &self.entries[*self.index.get(&name.to_ascii_lowercase())?];
+        let offset = entry.offset as u64 * SECTOR_SIZE;
+        let end = (offset + entry.size as u64 * SECTOR_SIZE).min(self.len);
+        let mut data = vec![0; end.checked_sub(offset)? as usize];
+        match read_exact_at(&self.file, &mut data, offset) {
+            Ok(()) => Some(data),
+            Err(e) => {
+                error!("Error reading {name} from IMG archive: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Ordered set of IMG archives, archives added later override files in earlier archives
+pub struct ImgArchives {
+    archives: Vec<ImgArchive>,
+    /// Number of archives coming from the game itself (gta3.img and gta3.dat),
+    /// archives added from the command line always come after these
+    num_game: usize,
+}
+
+impl ImgArchives {
+    pub fn new(base: &Path) -> Result<Self> {
+        Ok(Self {
+            archives: vec![ImgArchive::open(base)?],
+            num_game: 1,
+        })
+    }
+
+    // Adds an archive referenced by gta3.dat
+    pub fn add_game(&mut self, path: &Path) -> Result {
+        self.archives.insert(self.num_game, ImgArchive::open(path)?);
+        self.num_game += 1;
+        Ok(())
+    }
+
+    // Adds an archive with the highest priority
+    pub fn add(&mut self, path: &Path) -> Result {
+        self.archives.push(ImgArchive::open(path)?);
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.archives.iter().any(|a| a.contains(name))
+    }
+
+    pub fn get_file(&self, name: &str) -> Option<Vec<u8>> {
+        self.archives
+            .iter()
+            .rev()
+            .find(|a| a.contains(name))
+            .and_then(|a| a.get_file(name))
+    }
+
+    // Entries of all archives, files overridden by a later archive are left out
+    pub fn entries(&self) -> Vec<&DirEntry> {
+        let mut seen = HashSet::new();
+        self.archives
+            .iter()
+            .rev()
+            .flat_map(|a| &a.entries)
+            .filter(|e| seen.insert(e.name.to_ascii_lowercase()))
+            .collect()
+    }
+
+    // Names of all files in all archives, without duplicates
+    pub fn entry_names(&self) -> Vec<String> {
+        self.entries().into_iter().map(|e| e.name.clone()).collect()
+    }
+}
+
+/// The IMG archives of the running game. This is shared between the asset reader and systems,
+/// reads only take the lock for shared access so loader tasks never wait on each other.
+/// Archives are only added while gta3.dat is loaded.
+#[derive(Resource, Clone)]
+pub struct SharedArchives(Arc<RwLock<ImgArchives>>);
+
+impl SharedArchives {
+    pub fn new(archives: ImgArchives) -> Self {
+        Self(Arc::new(RwLock::new(archives)))
+    }
+
+    // A panic while holding the lock can't leave the archives half-modified, so poisoning is ignored
+    pub fn read(&self) -> RwLockReadGuard<'_, ImgArchives> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, ImgArchives> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -1,28 +1,59 @@
-use std::{ops::Index, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
-use crate::{utils::get_path, IMG};
+use crate::{
+    archive::SharedArchives,
+    def::ObjectFlags,
+    install::GameInstall,
+    material::GTAMaterial,
+    mesh::load_dff,
+    objects::ModelCollision,
+    raster::{
+        address_mode, bytes_per_pixel, decode_paletted, decode_pixels, filter_mode, generate_mips,
+        split_levels, Compression,
+    },
+    utils::get_mod_path,
+    MOD_DIRS,
+};
 use async_fs::File;
 use bevy::{
     asset::{
         io::{AssetReader, AssetReaderError, PathStream, Reader, VecReader},
         AssetLoader, LoadContext, RenderAssetUsages,
     },
+    image::{
+        CompressedImageFormatSupport, CompressedImageFormats, ImageAddressMode, ImageFilterMode,
+        ImageSampler, ImageSamplerDescriptor,
+    },
     prelude::*,
     render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    tasks::futures_lite::stream,
 };
-use nom_derive::Parse;
+use nom_derive::{nom::multi::many0, Parse};
 use num_traits::FromPrimitive;
-use rw_rs::bsf::{
-    tex::{RasterFormat, RpRasterPalette},
-    Chunk, ChunkContent,
+use rw_rs::{
+    bsf::{
+        tex::{RasterFormat, RpRasterPalette},
+        Chunk, ChunkContent,
+    },
+    col::CollV1,
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-pub struct GTAAssetReader;
+pub struct GTAAssetReader {
+    pub install: GameInstall,
+    pub img: SharedArchives,
+}
 
 // This exposes the files in gta3.img as files in a VFS
 impl AssetReader for GTAAssetReader {
     async fn read<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        if let Some(path) = get_mod_path(path) {
+            return Ok(Box::new(File::open(&path).await?) as Box<dyn Reader>);
+        }
         if path.components().count() == 1
             && path
                 .extension()
@@ -31,35 +62,33 @@ impl AssetReader for GTAAssetReader {
             let path_ext = path.extension();
             if let Some(path_ext) = path_ext {
                 {
-                    if let Some(file) = IMG
-                        .lock()
-                        .unwrap()
-                        .get_file(path.to_string_lossy().as_ref())
-                    {
+                    if let Some(file) = self.img.read().get_file(path.to_string_lossy().as_ref()) {
                         return Ok(Box::new(VecReader::new(file)) as Box<dyn Reader>);
                     }
                 }
                 if path_ext.eq_ignore_ascii_case("dff") {
-                    let Some(path) = get_path(&Path::new("models").join(path)) else {
+                    let Some(path) = self.install.get_path(&Path::new("models").join(path)) else {
                         return Err(AssetReaderError::NotFound(path.to_path_buf()));
                     };
                     return Ok(Box::new(File::open(&path).await?) as Box<dyn Reader>);
                 } else if path_ext.eq_ignore_ascii_case("txd") {
-                    let Some(path) = get_path(&Path::new("txd").join(path))
-                        .or_else(|| get_path(&Path::new("models").join(path)))
+                    let Some(path) = self
+                        .install
+                        .get_path(&Path::new("txd").join(path))
+                        .or_else(|| self.install.get_path(&Path::new("models").join(path)))
                     else {
                         return Err(AssetReaderError::NotFound(path.to_path_buf()));
                     };
                     return Ok(Box::new(File::open(&path).await?) as Box<dyn Reader>);
                 } else {
-                    let Some(path) = get_path(path) else {
+                    let Some(path) = self.install.get_path(path) else {
                         return Err(AssetReaderError::NotFound(path.to_path_buf()));
                     };
                     return Ok(Box::new(File::open(&path).await?) as Box<dyn Reader>);
                 }
             }
         }
-        if let Some(path) = get_path(path) {
+        if let Some(path) = self.install.get_path(path) {
             return Ok(Box::new(File::open(&path).await?) as Box<dyn Reader>);
         }
         Err(AssetReaderError::NotFound(path.to_path_buf()))
@@ -72,20 +101,385 @@ impl AssetReader for GTAAssetReader {
         Err::<Box<dyn Reader>, AssetReaderError>(AssetReaderError::NotFound(path.to_path_buf()))
     }
 
+    // The root directory contains the files in the mod directories and gta3.img merged with the
+    // files in the game directory, every other directory is resolved case-insensitively from it
     async fn read_directory<'a>(
         &'a self,
-        _path: &'a Path,
+        path: &'a Path,
     ) -> Result<Box<PathStream>, AssetReaderError> {
-        todo!("read_directory")
+        let Some(dir) = self.install.get_path(path).filter(|p| p.is_dir()) else {
+            return Err(AssetReaderError::NotFound(path.to_path_buf()));
+        };
+
+        let mut seen = HashSet::new();
+        let mut entries: Vec<PathBuf> = Vec::new();
+        if path.as_os_str().is_empty() {
+            for dir in MOD_DIRS.get().into_iter().flatten().rev() {
+                for file in std::fs::read_dir(dir)? {
+                    let Ok(file) = file else { continue };
+                    let file_name = file.file_name();
+                    if get_mod_path(Path::new(&file_name)).is_some()
+                        && seen.insert(file_name.to_string_lossy().to_ascii_lowercase())
+                    {
+                        entries.push(PathBuf::from(file_name));
+                    }
+                }
+            }
+            for name in self.img.read().entry_names() {
+                if seen.insert(name.to_ascii_lowercase()) {
+                    entries.push(PathBuf::from(name));
+                }
+            }
+        }
+        for file in std::fs::read_dir(&dir)? {
+            let Ok(file) = file else { continue };
+            let file_name = file.file_name();
+            if seen.insert(file_name.to_string_lossy().to_ascii_lowercase()) {
+                entries.push(path.join(file_name));
+            }
+        }
+
+        Ok(Box::new(stream::iter(entries)))
     }
 
-    async fn is_directory<'a>(&'a self, _path: &'a Path) -> Result<bool, AssetReaderError> {
-        todo!("is_directory")
+    async fn is_directory<'a>(&'a self, path: &'a Path) -> Result<bool, AssetReaderError> {
+        if path.as_os_str().is_empty() {
+            return Ok(true);
+        }
+        Ok(self.install.get_path(path).is_some_and(|p| p.is_dir()))
     }
 }
 
 #[derive(Default, TypePath)]
-pub struct TxdLoader;
+pub struct DffLoader;
+
+/// The TXD a model takes its textures from is defined in the IDE, not in the DFF itself
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DffSettings {
+    pub txd_name: String,
+    /// Parents of the TXD from the IDE txdp section, searched in order for missing textures
+    pub txd_parents: Vec<String>,
+    /// IDE flags, these change how the materials are drawn
+    pub flags: ObjectFlags,
+}
+
+impl AssetLoader for DffLoader {
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        let _ = reader.read_to_end(&mut bytes).await;
+        let Ok((_, bsf)) = Chunk::parse(&bytes) else {
+            return Err(DffError::InvalidDff);
+        };
+        if !bsf
+            .get_children()
+            .iter()
+            .any(|e| matches!(e.content, ChunkContent::GeometryList))
+        {
+            return Err(DffError::InvalidDff);
+        }
+
+        let txds = std::iter::once(&settings.txd_name)
+            .chain(&settings.txd_parents)
+            .map(|txd| load_context.load(format!("{txd}.txd")))
+            .collect();
+
+        let mut geometries = Vec::new();
+        let mut textures = Vec::new();
+        for (geo_num, geometry) in load_dff(&bsf).into_iter().enumerate() {
+            let mut handles = Vec::new();
+            for (mesh_num, (mesh, mut material, texture)) in geometry.into_iter().enumerate() {
+                material.apply_flags(settings.flags);
+                let mesh = load_context
+                    .add_labeled_asset(format!("Geometry{geo_num}/Mesh{mesh_num}"), mesh);
+                let material = load_context
+                    .add_labeled_asset(format!("Geometry{geo_num}/Material{mesh_num}"), material);
+                if let Some(texture) = texture {
+                    textures.push((material.clone(), texture));
+                }
+                handles.push((mesh, material));
+            }
+            geometries.push(handles);
+        }
+        Ok(Model {
+            geometries,
+            txds,
+            textures,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dff"]
+    }
+
+    type Asset = Model;
+
+    type Settings = DffSettings;
+
+    type Error = DffError;
+}
+
+/// A loaded DFF, shared by every instance of the model
+#[derive(Asset, TypePath, Debug, Default)]
+pub struct Model {
+    /// Meshes and their materials for every geometry in the DFF, the last one is the most detailed
+    pub geometries: Vec<Vec<(Handle<Mesh>, Handle<GTAMaterial>)>>,
+    /// The TXD of the model followed by its parents
+    pub txds: Vec<Handle<Txd>>,
+    /// Materials still waiting for their texture to be looked up in the TXDs
+    pub textures: Vec<(Handle<GTAMaterial>, String)>,
+}
+
+#[derive(Error, Debug)]
+pub enum DffError {
+    #[error("invalid DFF file")]
+    InvalidDff,
+}
+
+#[derive(Default, TypePath)]
+pub struct ColLoader;
+
+impl AssetLoader for ColLoader {
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        let _ = reader.read_to_end(&mut bytes).await;
+        let Ok((_, cols)) = many0(CollV1::parse)(&bytes) else {
+            return Err(ColError::InvalidCol);
+        };
+
+        let mut models = HashMap::new();
+        for col in cols {
+            let name = col.model_name.to_ascii_lowercase();
+            if models.insert(name, ModelCollision::new(&col)).is_some() {
+                warn!(
+                    "Duplicate collision model {} in {}",
+                    col.model_name,
+                    load_context.path()
+                );
+            }
+        }
+        Ok(CollisionArchive { models })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["col"]
+    }
+
+    type Asset = CollisionArchive;
+
+    type Settings = ();
+
+    type Error = ColError;
+}
+
+/// A loaded COL file, containing the colliders of every model in it
+#[derive(Asset, TypePath, Default)]
+pub struct CollisionArchive {
+    /// Colliders indexed by lowercase model name
+    pub models: HashMap<String, ModelCollision>,
+}
+
+#[derive(Error, Debug)]
+pub enum ColError {
+    #[error("invalid COL file")]
+    InvalidCol,
+}
+
+#[derive(Default, TypePath)]
+pub struct TxdLoader {
+    /// Block compressed rasters get uploaded as is if the GPU supports them
+    supported_compressed_formats: CompressedImageFormats,
+}
+
+impl TxdLoader {
+    // Decodes a D3D8 native raster into an image including all of its mip levels,
+    // also returns whether the raster has an alpha channel
+    pub fn decode_raster(
+        &self,
+        name: &str,
+        raster_format: u32,
+        compression: u8,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(Image, bool), TxdError> {
+        let unknown_format = || TxdError::UnknownFormat {
+            raster: name.to_owned(),
+            format: raster_format,
+        };
+        let truncated = || TxdError::TruncatedData {
+            raster: name.to_owned(),
+        };
+        let bad_palette = || TxdError::BadPalette {
+            raster: name.to_owned(),
+        };
+
+        let has_flag = |flag: RasterFormat| raster_format & (flag as u32) != 0;
+        let format = RasterFormat::from_u32(
+            raster_format
+                & !(RasterFormat::FormatExtAutoMipmap as u32)
+                & !(RasterFormat::FormatExtPal4 as u32)
+                & !(RasterFormat::FormatExtPal8 as u32)
+                & !(RasterFormat::FormatExtMipmap as u32),
+        )
+        .ok_or_else(unknown_format)?;
+        // There is nothing to decode, and generating mipmaps would index out of bounds
+        if width == 0 || height == 0 {
+            return Err(truncated());
+        }
+
+        let (levels_data, palette): (&[u8], Option<Vec<[u8; 4]>>) =
+            if has_flag(RasterFormat::FormatExtPal8) {
+                let (rest, palette) =
+                    RpRasterPalette::<256>::parse(data).map_err(|_| bad_palette())?;
+                (
+                    rest,
+                    Some(palette.0.iter().map(|c| [c.r, c.g, c.b, c.a]).collect()),
+                )
+            } else if has_flag(RasterFormat::FormatExtPal4) {
+                let (rest, palette) =
+                    RpRasterPalette::<32>::parse(data).map_err(|_| bad_palette())?;
+                (
+                    rest,
+                    Some(palette.0.iter().map(|c| [c.r, c.g, c.b, c.a]).collect()),
+                )
+            } else {
+                (data, None)
+            };
+        // The alpha of 888 palettes is undefined
+        let palette = palette.map(|mut p| {
+            if matches!(format, RasterFormat::Format888) {
+                p.iter_mut().for_each(|c| c[3] = 255);
+            }
+            p
+        });
+
+        let (w, h) = (width as usize, height as usize);
+        let max_levels = if has_flag(RasterFormat::FormatExtMipmap) {
+            (usize::BITS - w.max(h).leading_zeros()) as usize
+        } else {
+            1
+        };
+        let levels = split_levels(levels_data, max_levels)
+            .filter(|levels| !levels.is_empty())
+            .ok_or_else(truncated)?;
+        let compression = match (compression, &palette) {
+            (0, _) | (_, Some(_)) => None,
+            (c, None) => Some(Compression::from_d3d8(c).ok_or_else(unknown_format)?),
+        };
+        // Only the first level is stored if the mipmaps should be generated
+        let generate = has_flag(RasterFormat::FormatExtAutoMipmap) && levels.len() == 1;
+
+        // BC textures need to be a multiple of the block size
+        let upload_compressed = compression.filter(|c| {
+            self.supported_compressed_formats
+                .supports(c.texture_format())
+                && w % 4 == 0
+                && h % 4 == 0
+                && !generate
+        });
+
+        let mut pixels = Vec::new();
+        for (i, level) in levels.iter().enumerate() {
+            let (lw, lh) = ((w >> i).max(1), (h >> i).max(1));
+            match (compression, &palette) {
+                (Some(c), _) => {
+                    if level.len() < c.level_size(lw, lh) {
+                        return Err(truncated());
+                    }
+                    match upload_compressed {
+                        Some(_) => pixels.extend_from_slice(&level[..c.level_size(lw, lh)]),
+                        None => pixels.extend(c.decode(level, lw, lh)),
+                    }
+                }
+                (None, Some(palette)) => {
+                    if level.len() < lw * lh {
+                        return Err(truncated());
+                    }
+                    let decoded = decode_paletted(level, palette, lw, lh);
+                    pixels.extend(decoded.ok_or_else(bad_palette)?)
+                }
+                (None, None) => {
+                    let bpp = bytes_per_pixel(&format).ok_or_else(unknown_format)?;
+                    if level.len() < lw * lh * bpp {
+                        return Err(truncated());
+                    }
+                    let decoded = decode_pixels(&format, level, lw, lh);
+                    pixels.extend(decoded.ok_or_else(unknown_format)?)
+                }
+            }
+        }
+        let mut mip_level_count = levels.len() as u32;
+        if generate {
+            (pixels, mip_level_count) = generate_mips(pixels, w, h);
+        }
+
+        let mut image = Image::new_uninit(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            upload_compressed.map_or(TextureFormat::Rgba8UnormSrgb, |c| c.texture_format()),
+            RenderAssetUsages::default(),
+        );
+        image.data = Some(pixels);
+        image.texture_descriptor.mip_level_count = mip_level_count;
+
+        let has_alpha = match (&palette, compression) {
+            (Some(palette), _) => palette.iter().any(|c| c[3] != 255),
+            (None, Some(Compression::Dxt3)) => true,
+            _ => matches!(
+                format,
+                RasterFormat::Format8888 | RasterFormat::Format1555 | RasterFormat::Format4444
+            ),
+        };
+        Ok((image, has_alpha))
+    }
+
+    // Decodes every raster of a TXD file outside of the asset system, together with its name.
+    // Each raster has its own result so a bad raster doesn't hide the others.
+    pub fn decode_txd(
+        &self,
+        bytes: &[u8],
+    ) -> Result<Vec<(String, Result<(Image, bool), TxdError>)>, TxdError> {
+        let Ok((_, bsf)) = Chunk::parse(bytes) else {
+            return Err(TxdError::InvalidTxd);
+        };
+        if !matches!(bsf.content, ChunkContent::TextureDictionary) {
+            return Err(TxdError::InvalidTxd);
+        }
+        Ok(bsf
+            .get_children()
+            .iter()
+            .skip(1)
+            .filter_map(|chunk| match &chunk.content {
+                ChunkContent::Raster(raster) => Some((
+                    raster.name.clone(),
+                    self.decode_raster(
+                        &raster.name,
+                        raster.raster_format,
+                        raster.compression,
+                        raster.width.into(),
+                        raster.height.into(),
+                        &raster.data,
+                    ),
+                )),
+                _ => None,
+            })
+            .collect())
+    }
+}
 
 impl AssetLoader for TxdLoader {
     async fn load(
@@ -103,99 +497,53 @@ impl AssetLoader for TxdLoader {
             return Err(TxdError::InvalidTxd);
         }
 
-        let mut texture_vec = Vec::new();
+        let mut textures = HashMap::new();
 
-        for raster in &bsf.get_children()[1..] {
+        for raster in bsf.get_children().iter().skip(1) {
             if let ChunkContent::Raster(raster) = &raster.content {
-                let mut data: Vec<u8> = Vec::new();
-
-                let raster_format = RasterFormat::from_u32(
-                    raster.raster_format
-                        & !(RasterFormat::FormatExtAutoMipmap as u32)
-                        & !(RasterFormat::FormatExtPal4 as u32)
-                        & !(RasterFormat::FormatExtPal8 as u32)
-                        & !(RasterFormat::FormatExtMipmap as u32),
-                )
-                .unwrap();
-
-                if (raster.raster_format & (RasterFormat::FormatExtPal8 as u32)) != 0 {
-                    let (indices, palette) = RpRasterPalette::<256>::parse(&raster.data).unwrap();
-                    let indices = &indices[4..];
-                    for h in 0..(raster.height as usize) {
-                        for w in 0..(raster.width as usize) {
-                            let index = indices[w + (h * (raster.width as usize))];
-                            let color = palette.0[index as usize];
-                            data.push(color.r);
-                            data.push(color.g);
-                            data.push(color.b);
-                            data.push(color.a);
-                        }
-                    }
-                } else if (raster.raster_format & (RasterFormat::FormatExtPal4 as u32)) != 0 {
-                    let (indices, palette) = RpRasterPalette::<32>::parse(&raster.data).unwrap();
-                    let indices = &indices[4..];
-                    for h in 0..(raster.height as usize) {
-                        for w in 0..(raster.width as usize) {
-                            let index = indices[w + (h * (raster.width as usize))];
-                            let color = palette.0[index as usize];
-                            data.push(color.r);
-                            data.push(color.g);
-                            data.push(color.b);
-                            data.push(color.a);
-                        }
+                // A single bad raster shouldn't take the rest of the dictionary with it
+                let (mut image, has_alpha) = match self.decode_raster(
+                    &raster.name,
+                    raster.raster_format,
+                    raster.compression,
+                    raster.width.into(),
+                    raster.height.into(),
+                    &raster.data,
+                ) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        error!("Skipping raster in {}: {e}", load_context.path());
+                        continue;
                     }
-                } else if matches!(raster_format, RasterFormat::Format1555) {
-                    // TODO: Support DXT
-                    for p in raster.data[4..].chunks_exact(2) {
-                        let p = u16::from_le_bytes([p[0], p[1]]);
-                        let mut a = (p >> 15) as u8;
-                        let r = ((p >> 10) & 0b11111) as u8;
-                        let g = ((p >> 5) & 0b11111) as u8;
-                        let b = (p & 0b11111) as u8;
-
-                        if a != 0 {
-                            a = 255
-                        }
-
-                        data.push(r);
-                        data.push(g);
-                        data.push(b);
-                        data.push(a);
-                    }
-                } else {
-                    data = raster.data[4..].to_vec();
-                }
+                };
+
+                let (filter, mipmap_filter) = filter_mode(&raster.filtering);
+                let address_mode = raster.addressing.each_ref().map(address_mode);
+                image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                    address_mode_u: address_mode[0],
+                    address_mode_v: address_mode[1],
+                    mag_filter: filter,
+                    min_filter: filter,
+                    mipmap_filter,
+                    ..Default::default()
+                });
 
-                let format = match raster_format {
-                    RasterFormat::Format8888 => TextureFormat::Rgba8UnormSrgb,
-                    RasterFormat::Format888 => TextureFormat::Rgba8UnormSrgb,
-                    RasterFormat::Format1555 => TextureFormat::Rgba8UnormSrgb,
-                    _ => unimplemented!(),
+                let name = raster.name.to_ascii_lowercase();
+                let texture = TxdTexture {
+                    image: load_context.add_labeled_asset(name.clone(), image),
+                    raster_format: raster.raster_format,
+                    has_alpha,
+                    filter,
+                    mipmap_filter,
+                    address_mode,
                 };
-                let image = Image::new(
-                    Extent3d {
-                        width: raster.width.into(),
-                        height: raster.height.into(),
-                        depth_or_array_layers: 1,
-                    },
-                    TextureDimension::D2,
-                    data,
-                    format,
-                    RenderAssetUsages::default(),
-                );
-                texture_vec.push(
-                    load_context
-                        .labeled_asset_scope::<_, ()>(raster.name.to_ascii_lowercase(), |_lc| {
-                            Ok(image)
-                        })
-                        .unwrap(),
-                );
+                textures.insert(name, texture);
             } else if !matches!(raster.content, ChunkContent::Extension) {
                 error!("Unexpected type {:?} found in TXD file", raster.content);
                 continue;
             }
         }
-        Ok(Txd(texture_vec))
+        Ok(Txd { textures })
     }
 
     fn extensions(&self) -> &[&str] {
@@ -209,19 +557,163 @@ impl AssetLoader for TxdLoader {
     type Error = TxdError;
 }
 
-#[derive(Asset, Reflect, Clone, Debug, Default)]
-pub struct Txd(pub Vec<Handle<Image>>);
+/// Registers the TXD loader once we know which compressed formats the GPU supports
+pub struct TxdPlugin;
 
-impl Index<usize> for Txd {
-    type Output = Handle<Image>;
+impl Plugin for TxdPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Txd>();
+    }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.0[index]
+    fn finish(&self, app: &mut App) {
+        let supported_compressed_formats = app
+            .world()
+            .get_resource::<CompressedImageFormatSupport>()
+            .map(|s| s.0)
+            .unwrap_or(CompressedImageFormats::NONE);
+        app.register_asset_loader(TxdLoader {
+            supported_compressed_formats,
+        });
     }
 }
 
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct Txd {
+    /// Textures indexed by their lowercase raster name
+    pub textures: HashMap<String, TxdTexture>,
+}
+
+impl Txd {
+    pub fn get(&self, name: &str) -> Option<&TxdTexture> {
+        self.textures.get(&name.to_ascii_lowercase())
+    }
+}
+
+/// A raster from a TXD together with its original metadata
+#[derive(Clone, Debug)]
+pub struct TxdTexture {
+    pub image: Handle<Image>,
+    /// RenderWare raster format including the extension flags
+    pub raster_format: u32,
+    pub has_alpha: bool,
+    pub filter: ImageFilterMode,
+    pub mipmap_filter: ImageFilterMode,
+    pub address_mode: [ImageAddressMode; 2],
+}
+
 #[derive(Error, Debug)]
 pub enum TxdError {
     #[error("invalid TXD file")]
     InvalidTxd,
+    #[error("raster {raster} has unknown format {format:#x}")]
+    UnknownFormat { raster: String, format: u32 },
+    #[error("raster {raster} is truncated")]
+    TruncatedData { raster: String },
+    #[error("raster {raster} has an invalid palette")]
+    BadPalette { raster: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Prefixes raster data with its size, like each mip level in a D3D8 raster
+    fn level(data: &[u8]) -> Vec<u8> {
+        (data.len() as u32)
+            .to_le_bytes()
+            .into_iter()
+            .chain(data.iter().copied())
+            .collect()
+    }
+
+    fn try_decode(
+        format: u32,
+        compression: u8,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Result<(Image, bool), TxdError> {
+        TxdLoader::default().decode_raster("test", format, compression, width, height, data)
+    }
+
+    fn decode(format: RasterFormat, compression: u8, size: u32, data: &[u8]) -> Vec<u8> {
+        let (image, _) = try_decode(format as u32, compression, size, size, data).unwrap();
+        image.data.unwrap()
+    }
+
+    #[test]
+    fn lum8_with_dxt_sized_data_stays_uncompressed() {
+        // 16 bytes is also the size of a 4x4 DXT3 level
+        let rgba = decode(RasterFormat::FormatLum8, 0, 4, &level(&[100; 16]));
+        assert_eq!(rgba.len(), 4 * 4 * 4);
+        assert_eq!(&rgba[..4], &[100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn dxt1_raster_is_decoded_without_bc_support() {
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0, 0, 0, 0];
+        let rgba = decode(RasterFormat::Format565, 1, 4, &level(&block));
+        assert_eq!(rgba.len(), 4 * 4 * 4);
+        assert_eq!(&rgba[..4], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn zero_sized_rasters_are_rejected() {
+        let format = RasterFormat::Format565 as u32 | RasterFormat::FormatExtAutoMipmap as u32;
+        for (width, height) in [(0, 4), (4, 0), (0, 0)] {
+            assert!(matches!(
+                try_decode(format, 0, width, height, &level(&[])),
+                Err(TxdError::TruncatedData { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn truncated_rasters_fail_to_decode() {
+        let pal8 = RasterFormat::Format8888 as u32 | RasterFormat::FormatExtPal8 as u32;
+        let pal4 = RasterFormat::Format8888 as u32 | RasterFormat::FormatExtPal4 as u32;
+        let auto_mipmap = RasterFormat::Format565 as u32 | RasterFormat::FormatExtAutoMipmap as u32;
+        // 4x4 rasters of every kind the loader decodes
+        let rasters = [
+            (RasterFormat::Format8888 as u32, 0, level(&[0x80; 64])),
+            (RasterFormat::Format888 as u32, 0, level(&[0x80; 64])),
+            (RasterFormat::Format1555 as u32, 0, level(&[0x80; 32])),
+            (RasterFormat::Format555 as u32, 0, level(&[0x80; 32])),
+            (RasterFormat::Format565 as u32, 0, level(&[0x80; 32])),
+            (RasterFormat::Format4444 as u32, 0, level(&[0x80; 32])),
+            (RasterFormat::FormatLum8 as u32, 0, level(&[0x80; 16])),
+            (RasterFormat::Format565 as u32, 1, level(&[0x80; 8])),
+            (RasterFormat::Format4444 as u32, 3, level(&[0x80; 16])),
+            (pal8, 0, [vec![0xFF; 256 * 4], level(&[1; 16])].concat()),
+            (pal4, 0, [vec![0xFF; 32 * 4], level(&[1; 16])].concat()),
+            (auto_mipmap, 0, level(&[0x80; 32])),
+        ];
+        for (format, compression, data) in rasters {
+            assert!(try_decode(format, compression, 4, 4, &data).is_ok());
+            for len in 0..data.len() {
+                assert!(
+                    try_decode(format, compression, 4, 4, &data[..len]).is_err(),
+                    "format {format:#x} cut to {len} bytes decoded"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mip_chain_cut_inside_a_level_fails() {
+        let format = RasterFormat::Format565 as u32 | RasterFormat::FormatExtMipmap as u32;
+        let data = [level(&[0x80; 32]), level(&[0x80; 8]), level(&[0x80; 2])].concat();
+        let (image, _) = try_decode(format, 0, 4, 4, &data).unwrap();
+        assert_eq!(image.texture_descriptor.mip_level_count, 3);
+        // Stopping between levels leaves a shorter but valid chain
+        let boundaries = [36, 48];
+        for len in 0..data.len() {
+            let decoded = try_decode(format, 0, 4, 4, &data[..len]);
+            assert_eq!(
+                decoded.is_ok(),
+                boundaries.contains(&len),
+                "cut to {len} bytes"
+            );
+        }
+    }
 }
@@ -0,0 +1,267 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use clap::Subcommand;
+
+use crate::{
+    archive::{ImgArchives, SECTOR_SIZE},
+    assets::TxdLoader,
+    doctor,
+    img_edit::ImgEditor,
+    install::GameInstall,
+    utils::glob_match,
+};
+
+/// Tools that run without starting the game
+#[derive(Subcommand)]
+pub enum Command {
+    /// Inspect and extract IMG archives
+    Img {
+        /// Archive to use instead of gta3.img and the archives passed with --img
+        #[arg(long)]
+        archive: Option<PathBuf>,
+        #[command(subcommand)]
+        command: ImgCommand,
+    },
+    /// Inspect and export texture dictionaries
+    Txd {
+        #[command(subcommand)]
+        command: TxdCommand,
+    },
+    /// Check the game directory for missing or corrupt files
+    Doctor,
+}
+
+#[derive(Subcommand)]
+pub enum ImgCommand {
+    /// List all files with their size in bytes
+    List,
+    /// Extract all files matching a name or a glob pattern like "*.txd"
+    Extract {
+        pattern: String,
+        /// Directory the files are written to
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    #[command(flatten)]
+    Edit(ImgEditCommand),
+}
+
+/// Commands that rewrite a single archive
+#[derive(Subcommand)]
+pub enum ImgEditCommand {
+    /// Add files to the archive, replacing entries with the same name
+    Add {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Remove entries from the archive
+    Remove {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+    /// Rewrite the archive without the unused space between entries
+    Rebuild,
+}
+
+#[derive(Subcommand)]
+pub enum TxdCommand {
+    /// Write every raster of a TXD file to a PNG image
+    Export { file: PathBuf, outdir: PathBuf },
+}
+
+pub fn run(command: Command, gta_dir: &Path, extra_imgs: &[PathBuf]) -> AppExit {
+    let result = match command {
+        Command::Img { archive, command } => run_img(gta_dir, archive, extra_imgs, command),
+        Command::Txd {
+            command: TxdCommand::Export { file, outdir },
+        } => export_txd(&file, &outdir),
+        Command::Doctor => match doctor::run(gta_dir, extra_imgs) {
+            Ok(true) => Ok(()),
+            Ok(false) => return AppExit::error(),
+            Err(e) => Err(e),
+        },
+    };
+    match result {
+        Ok(()) => AppExit::Success,
+        Err(e) => {
+            eprintln!("error: {e}");
+            AppExit::error()
+        }
+    }
+}
+
+fn run_img(
+    gta_dir: &Path,
+    archive: Option<PathBuf>,
+    extra_imgs: &[PathBuf],
+    command: ImgCommand,
+) -> Result {
+    // Only look for the game when no archive was given
+    let default_archive = || -> Result<PathBuf> {
+        let install = GameInstall::detect(gta_dir)?;
+        Ok(install.root.join(install.img_file()))
+    };
+
+    // Reading looks through every archive the game loads, unless one was given
+    let open_archives = || -> Result<ImgArchives> {
+        match &archive {
+            Some(path) => ImgArchives::new(path),
+            None => {
+                let mut archives = ImgArchives::new(&default_archive()?)?;
+                for img in extra_imgs {
+                    archives.add(img)?;
+                }
+                Ok(archives)
+            }
+        }
+    };
+
+    match command {
+        ImgCommand::List => {
+            for entry in open_archives()?.entries() {
+                println!("{:<24} {:>10}", entry.name, entry.size as u64 * SECTOR_SIZE);
+            }
+        }
+        ImgCommand::Extract { pattern, out } => {
+            let archives = open_archives()?;
+            let names: Vec<String> = archives
+                .entry_names()
+                .into_iter()
+                .filter(|name| glob_match(&pattern, name))
+                .collect();
+            if names.is_empty() {
+                return Err(format!("no files matching {pattern}").into());
+            }
+            fs::create_dir_all(&out)?;
+            for name in names {
+                let Some(data) = archives.get_file(&name) else {
+                    eprintln!("error: could not read {name}");
+                    continue;
+                };
+                let Some(path) = output_path(&out, &name) else {
+                    eprintln!("error: skipping {name}: not a plain file name");
+                    continue;
+                };
+                fs::write(path, data)?;
+                println!("{name}");
+            }
+        }
+        // Edits only ever apply to a single archive
+        ImgCommand::Edit(command) => {
+            let path = match &archive {
+                Some(path) => path.clone(),
+                None => default_archive()?,
+            };
+            edit_img(&path, command)?;
+        }
+    }
+    Ok(())
+}
+
+fn edit_img(path: &Path, command: ImgEditCommand) -> Result {
+    let mut editor = ImgEditor::open(path)?;
+    match command {
+        ImgEditCommand::Add { files } => {
+            for file in files {
+                let Some(name) = file.file_name().and_then(|n| n.to_str()) else {
+                    return Err(format!("invalid file name {}", file.display()).into());
+                };
+                let replaced = editor.add(name, fs::read(&file)?)?;
+                println!("{} {name}", if replaced { "replaced" } else { "added" });
+            }
+        }
+        ImgEditCommand::Remove { names } => {
+            for name in names {
+                if !editor.remove(&name) {
+                    return Err(format!("{name} not found in {}", path.display()).into());
+                }
+                println!("removed {name}");
+            }
+        }
+        ImgEditCommand::Rebuild => {}
+    }
+
+    let old_size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let new_size = editor.write()?;
+    println!(
+        "wrote {} ({new_size} bytes, was {old_size} bytes)",
+        path.display()
+    );
+    Ok(())
+}
+
+// Decodes the rasters the same way the TXD loader does and writes the first level of each
+fn export_txd(file: &Path, outdir: &Path) -> Result {
+    // Without compressed formats every raster gets decoded to RGBA8
+    let rasters = TxdLoader::default().decode_txd(&fs::read(file)?)?;
+    fs::create_dir_all(outdir)?;
+
+    for (name, decoded) in rasters {
+        let texture = match decoded {
+            Ok((texture, _)) => texture,
+            Err(e) => {
+                eprintln!("error: skipping raster: {e}");
+                continue;
+            }
+        };
+        let size = texture.size();
+        let mut pixels = texture.data.unwrap_or_default();
+        pixels.truncate(size.x as usize * size.y as usize * 4);
+        let Some(png) = image::RgbaImage::from_raw(size.x, size.y, pixels) else {
+            eprintln!("error: skipping raster {name}: no pixel data");
+            continue;
+        };
+        let Some(path) = output_path(outdir, &format!("{name}.png")) else {
+            eprintln!("error: skipping raster {name}: not a plain file name");
+            continue;
+        };
+        png.save(&path)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+// Names come from the files being read, so only accept plain file names that stay inside dir
+fn output_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.contains(['/', '\\']) {
+        return None;
+    }
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file)), None) => Some(dir.join(file)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_path_accepts_plain_names() {
+        let dir = Path::new("out");
+        assert_eq!(output_path(dir, "bar.dff"), Some(dir.join("bar.dff")));
+        assert_eq!(output_path(dir, "..txd"), Some(dir.join("..txd")));
+    }
+
+    #[test]
+    fn output_path_rejects_escaping_names() {
+        let dir = Path::new("out");
+        for name in [
+            "",
+            ".",
+            "..",
+            "../x.txd",
+            "a/b.txd",
+            "a\\b.txd",
+            "/etc/passwd",
+            "..\\x",
+        ] {
+            assert_eq!(output_path(dir, name), None, "{name}");
+        }
+    }
+}
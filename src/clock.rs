@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+
+use crate::{
+    def::ObjTime,
+    objects::{set_hidden, HiddenBy},
+};
+
+/// The in-game time of day
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct GameClock {
+    pub hour: u8,
+    pub minute: u8,
+    /// Real milliseconds per in-game minute, the game uses one second
+    pub ms_per_minute: u32,
+    /// Stops the clock without changing the time
+    pub paused: bool,
+    /// Real time that didn't add up to a full minute yet
+    elapsed_ms: u32,
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        Self {
+            hour: 12,
+            minute: 0,
+            ms_per_minute: 1000,
+            paused: false,
+            elapsed_ms: 0,
+        }
+    }
+}
+
+impl GameClock {
+    pub fn set(&mut self, hour: u8, minute: u8) {
+        self.hour = hour % 24;
+        self.minute = minute % 60;
+        self.elapsed_ms = 0;
+    }
+}
+
+/// Object that is only visible during some hours, from the tobj section of an IDE file
+#[derive(Component)]
+pub struct TimedObject(pub ObjTime);
+
+fn advance_clock(time: Res<Time>, mut clock: ResMut<GameClock>) {
+    if clock.paused || clock.ms_per_minute == 0 {
+        return;
+    }
+    // Only touch the clock when a minute passed, so systems can react to it changing
+    let elapsed = clock.elapsed_ms + time.delta().as_millis() as u32;
+    let minutes = elapsed / clock.ms_per_minute;
+    if minutes == 0 {
+        clock.bypass_change_detection().elapsed_ms = elapsed;
+        return;
+    }
+    clock.elapsed_ms = elapsed % clock.ms_per_minute;
+    let total = clock.hour as u32 * 60 + clock.minute as u32 + minutes;
+    clock.hour = ((total / 60) % 24) as u8;
+    clock.minute = (total % 60) as u8;
+}
+
+// Shows and hides timed objects when the hour changes, and sets up newly spawned ones
+fn update_timed_objects(
+    clock: Res<GameClock>,
+    mut last_hour: Local<Option<u8>>,
+    mut objects: Query<(&TimedObject, &mut HiddenBy)>,
+    added: Query<Entity, Added<TimedObject>>,
+) {
+    let hour_changed = *last_hour != Some(clock.hour);
+    if !hour_changed && added.is_empty() {
+        return;
+    }
+    *last_hour = Some(clock.hour);
+
+    for (timed, mut hidden) in &mut objects {
+        set_hidden(&mut hidden, HiddenBy::TIME, !timed.0.is_visible(clock.hour));
+    }
+}
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameClock>()
+            .register_type::<GameClock>()
+            .add_systems(Update, (advance_clock, update_timed_objects).chain());
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use bevy::{camera::visibility::VisibilitySystems, prelude::*};
+
+use crate::{
+    def::{IplCull, ZoneAttributes},
+    objects::{apply_hidden, set_hidden, HiddenBy},
+    to_xzy,
+};
+
+/// Width of the grid cells zones are sorted into
+const CELL_SIZE: f32 = 100.0;
+
+/// A box from a cull section in world space
+#[derive(Clone, Debug)]
+pub struct CullZone {
+    pub center: Vec3,
+    pub min: Vec3,
+    pub max: Vec3,
+    pub attributes: ZoneAttributes,
+    pub wanted_level_drop: u32,
+}
+
+impl CullZone {
+    pub fn contains(&self, pos: Vec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmple(self.max).all()
+    }
+
+    // Zones without NOT_CULL_ZONE hide far-off objects while the camera is inside them
+    pub fn culls(&self) -> bool {
+        !self.attributes.contains(ZoneAttributes::NOT_CULL_ZONE)
+    }
+}
+
+/// Every cull zone, sorted into a grid on the ground plane for position lookups
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct CullZones {
+    #[reflect(ignore)]
+    zones: Vec<CullZone>,
+    #[reflect(ignore)]
+    grid: HashMap<IVec2, Vec<usize>>,
+    /// Objects further than this from the camera are hidden while it is in a cull zone, unless
+    /// they are in one of the zones the camera is in
+    pub cull_distance: f32,
+    pub enabled: bool,
+}
+
+impl Default for CullZones {
+    fn default() -> Self {
+        Self {
+            zones: Vec::new(),
+            grid: HashMap::new(),
+            cull_distance: 150.0,
+            enabled: true,
+        }
+    }
+}
+
+impl CullZones {
+    pub fn add(&mut self, cull: IplCull) {
+        let [a, b] = cull.corners.map(|corner| Vec3::from(to_xzy(corner)));
+        let zone = CullZone {
+            center: Vec3::from(to_xzy(cull.center)),
+            min: a.min(b),
+            max: a.max(b),
+            attributes: cull.attributes,
+            wanted_level_drop: cull.wanted_level_drop,
+        };
+        let (min, max) = (cell(zone.min), cell(zone.max));
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                self.grid
+                    .entry(IVec2::new(x, y))
+                    .or_default()
+                    .push(self.zones.len());
+            }
+        }
+        self.zones.push(zone);
+    }
+
+    pub fn zones(&self) -> &[CullZone] {
+        &self.zones
+    }
+
+    pub fn zones_at(&self, pos: Vec3) -> impl Iterator<Item = &CullZone> {
+        self.grid
+            .get(&cell(pos))
+            .into_iter()
+            .flatten()
+            .map(|&i| &self.zones[i])
+            .filter(move |zone| zone.contains(pos))
+    }
+
+    // Attributes of every zone containing a position combined
+    pub fn attributes_at(&self, pos: Vec3) -> ZoneAttributes {
+        self.zones_at(pos)
+            .fold(ZoneAttributes::empty(), |attributes, zone| {
+                attributes | zone.attributes
+            })
+    }
+
+    pub fn wanted_level_drop_at(&self, pos: Vec3) -> u32 {
+        self.zones_at(pos)
+            .map(|zone| zone.wanted_level_drop)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+// Cells of the grid are indexed by world x and z
+fn cell(pos: Vec3) -> IVec2 {
+    (pos.xz() / CELL_SIZE).floor().as_ivec2()
+}
+
+// Hides objects that are far away from the camera while it is in a cull zone. The game uses
+// visibility lists computed offline for this, the distance check is a cheaper stand-in.
+fn cull_objects(
+    cull_zones: Res<CullZones>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+    mut objects: Query<(&GlobalTransform, &mut HiddenBy)>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    let camera = camera.translation();
+    let active: Vec<&CullZone> = if cull_zones.enabled {
+        cull_zones.zones_at(camera).filter(|z| z.culls()).collect()
+    } else {
+        Vec::new()
+    };
+    let max_distance = cull_zones.cull_distance * cull_zones.cull_distance;
+    for (transform, mut hidden) in &mut objects {
+        let pos = transform.translation();
+        let culled = !active.is_empty()
+            && pos.distance_squared(camera) > max_distance
+            && !active.iter().any(|zone| zone.contains(pos));
+        set_hidden(&mut hidden, HiddenBy::CULL_ZONE, culled);
+    }
+}
+
+pub struct CullPlugin;
+
+impl Plugin for CullPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CullZones>()
+            .register_type::<CullZones>()
+            .add_systems(
+                PostUpdate,
+                cull_objects
+                    .before(apply_hidden)
+                    .before(VisibilitySystems::VisibilityPropagate),
+            );
+    }
+}
@@ -1,33 +1,58 @@
 use std::{
     collections::HashMap,
     io::{Cursor, Seek, SeekFrom},
+    path::Path,
 };
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use binrw::BinReaderExt;
-use nom_derive::nom::multi::many0;
-use rw_rs::col::CollV1;
 
 use crate::{
+    archive::SharedArchives,
+    assets::CollisionArchive,
+    cull::CullZones,
+    def::{parse_def, DefKind, DefRecord, Effect2d, IdeObj, PathGroup, PedDef, VehicleDef},
+    install::GameInstall,
     objects::SpawnObject,
+    occlusion::Occluders,
+    paths::PathGraph,
     to_xzy,
-    utils::{get_path, to_path},
-    GTA_DIR,
+    utils::to_path,
+    zones::ZoneRegistry,
 };
 
+/// Resources filled from the zone, cull, occl and path sections of the IPL files
+#[derive(SystemParam)]
+pub struct MapAreas<'w> {
+    pub zones: ResMut<'w, ZoneRegistry>,
+    pub cull: ResMut<'w, CullZones>,
+    pub occluders: ResMut<'w, Occluders>,
+    pub paths: ResMut<'w, PathGraph>,
+}
+
 #[derive(Resource)]
 pub struct GameData {
     pub ide: Ide,
-    /// Hashmap of collision files indexed by ModelName
-    pub col: HashMap<String, CollV1>,
+    /// Collision files in load order, later files override earlier ones
+    pub col: Vec<Handle<CollisionArchive>>,
     pub water_level: [f32; 128 * 128],
 }
 
 impl GameData {
-    pub fn load_dat(&mut self, commands: &mut Commands) -> Result {
-        let dat = std::fs::read_to_string(GTA_DIR.join("data/gta3.dat"))?;
+    pub fn load_dat(
+        &mut self,
+        install: &GameInstall,
+        commands: &mut Commands,
+        server: &AssetServer,
+        img: &SharedArchives,
+        areas: &mut MapAreas,
+    ) -> Result {
+        let path = install
+            .get_path(Path::new(install.dat_file()))
+            .ok_or(format!("{} not found!", install.dat_file()))?;
+        let dat = std::fs::read_to_string(path)?;
         let lines = dat.split('\n').map(|e| e.trim()).collect::<Vec<_>>();
-        for line in lines {
+        for (number, line) in lines.into_iter().enumerate() {
             let words = line
                 .split_whitespace()
                 .take_while(|s| !s.contains('#'))
@@ -37,198 +62,116 @@ impl GameData {
             }
 
             let ty = words[0].to_lowercase();
+            // Directives missing their path are skipped like unknown ones
+            let word = |i: usize| {
+                let word = words.get(i).copied();
+                if word.is_none() {
+                    error!(
+                        "{}:{}: {ty} without a path, ignoring",
+                        install.dat_file(),
+                        number + 1
+                    );
+                }
+                word
+            };
             match ty.as_str() {
-                "ide" | "mapzone" | "ipl" => self.load_def(ty.as_str(), words[1], commands)?,
-                "splash" => {}
-                "colfile" => self.load_colfile(words[2])?,
-                _ => todo!(),
-            }
-        }
-        Ok(())
-    }
-
-    pub fn load_def(&mut self, ty: &str, path: &str, commands: &mut Commands) -> Result {
-        let path = get_path(&to_path(path)).ok_or(format!("{} not found!", path))?;
-        let dat = std::fs::read_to_string(&path)?;
-        let lines = dat.split('\n').map(|e| e.trim()).collect::<Vec<_>>();
-
-        let mut section = String::new();
-        for (linecount, line) in lines.into_iter().enumerate() {
-            let linecount = linecount + 1;
-            let line = line.replace(',', "");
-            let words = line
-                .split_whitespace()
-                .take_while(|s| !s.contains('#'))
-                .collect::<Vec<_>>();
-
-            if words.is_empty() {
-                continue;
-            }
-
-            if words.len() == 1 {
-                if words[0] == "end" {
-                    section = "".to_string();
-                } else {
-                    section = words[0].to_owned();
+                "ide" | "mapzone" | "ipl" => {
+                    if let Some(file) = word(1) {
+                        self.load_def(install, ty.as_str(), file, commands, areas)?
+                    }
                 }
-                continue;
-            }
-            match section.to_lowercase().as_str() {
-                // IDE
-                "objs" => {
-                    let mut obj = IdeObj {
-                        id: words[0].parse().unwrap(),
-                        model_name: words[1].to_string(),
-                        txd_name: words[2].to_string(),
-                        mesh_count: 0,
-                        draw_distance: [0.0; 3],
-                        flags: 0,
-                    };
-                    match words.len() {
-                        n @ 6..=8 => {
-                            let n = n - 5;
-                            obj.mesh_count = n as u32;
-                            for i in 0..n {
-                                obj.draw_distance[i] = words[4 + i].parse().unwrap();
-                            }
-                            obj.flags = words[4 + n].parse().unwrap();
-                        }
-                        5 => {
-                            obj.mesh_count = 1;
-                            obj.draw_distance[0] = words[3].parse().unwrap();
-                            obj.flags = words[4].parse().unwrap();
-                        }
-                        _ => {
-                            error!("Error parsing obj on line {} of file {}, invalid amount of arguments", linecount, &path.display());
-                            continue;
-                        }
+                "splash" => {}
+                "colfile" => {
+                    if let Some(file) = word(2) {
+                        self.load_colfile(file, server)
                     }
-                    self.ide.objs.insert(obj.id, obj);
                 }
-
-                "tobj" => {
-                    // TODO: parse TimeOn & TimeOff
-                    let mut obj = IdeObj {
-                        id: words[0].parse().unwrap(),
-                        model_name: words[1].to_string(),
-                        txd_name: words[2].to_string(),
-                        mesh_count: 0,
-                        draw_distance: [0.0; 3],
-                        flags: 0,
+                // Mods list archives that may not be installed, the game runs without them
+                "img" | "cdimage" => {
+                    let Some(file) = word(1) else {
+                        continue;
                     };
-                    match words.len() {
-                        n @ 8..=10 => {
-                            let n = n - 7;
-                            obj.mesh_count = n as u32;
-                            for i in 0..n {
-                                obj.draw_distance[i] = words[4 + i].parse().unwrap();
+                    match install.get_path(&to_path(file)) {
+                        Some(path) => {
+                            if let Err(e) = img.write().add_game(&path) {
+                                error!("Skipping {file}: {e}");
                             }
-                            obj.flags = words[4 + n].parse().unwrap();
-                        }
-                        7 => {
-                            obj.mesh_count = 1;
-                            obj.draw_distance[0] = words[3].parse().unwrap();
-                            obj.flags = words[4].parse().unwrap();
-                        }
-                        _ => {
-                            error!("Error parsing obj on line {} of file {}, invalid amount of arguments", linecount, &path.display());
-                            continue;
                         }
+                        None => error!("Skipping {file}: not found"),
                     }
-                    self.ide.objs.insert(obj.id, obj);
                 }
+                s => warn!("Unknown directive {} found in gta3.dat, ignoring", s),
+            }
+        }
+        Ok(())
+    }
 
-                "hier" => {}
-
-                "cars" => {}
-
-                "peds" => {}
-
-                "path" if ty == "ide" => {}
-
-                "2dfx" => {}
-
-                "weap" => {}
-
-                "anim" => {}
-
-                "txdp" => {}
-
-                // IPL
-                "inst" => {
-                    let name = String::from(words[1]);
-
-                    let pos = to_xzy([
-                        words[2].parse::<f32>().unwrap(),
-                        words[3].parse::<f32>().unwrap(),
-                        words[4].parse::<f32>().unwrap(),
-                    ]);
-
-                    let scale = [
-                        words[5].parse().unwrap(),
-                        words[6].parse().unwrap(),
-                        words[7].parse().unwrap(),
-                    ];
-
-                    let rot = Quat::from_array([
-                        words[8].parse::<f32>().unwrap(),
-                        -words[10].parse::<f32>().unwrap(),
-                        -words[9].parse::<f32>().unwrap(),
-                        words[11].parse::<f32>().unwrap(),
-                    ])
-                    .normalize();
+    pub fn load_def(
+        &mut self,
+        install: &GameInstall,
+        ty: &str,
+        path: &str,
+        commands: &mut Commands,
+        areas: &mut MapAreas,
+    ) -> Result {
+        let path = install
+            .get_path(&to_path(path))
+            .ok_or(format!("{} not found!", path))?;
+        let text = String::from_utf8_lossy(&std::fs::read(&path)?).into_owned();
+        let kind = if ty == "ide" {
+            DefKind::Ide
+        } else {
+            DefKind::Ipl
+        };
+        let def = parse_def(
+            kind,
+            install.inst_layout(),
+            &path.display().to_string(),
+            &text,
+        );
+        // A broken line only loses that line
+        for e in def.errors {
+            error!("{e}");
+        }
 
+        for (_, record) in def.records {
+            let Some(record) = self.ide.add(record) else {
+                continue;
+            };
+            match record {
+                // Path groups without a model are placed in the world directly
+                DefRecord::Path(group) => areas.paths.add_group(&group, &Transform::IDENTITY),
+                DefRecord::Inst(inst) => {
+                    let [x, y, z, w] = inst.rot;
                     commands.trigger(SpawnObject {
-                        id: words[0].parse::<u32>().unwrap(),
-                        name,
-                        pos,
-                        scale,
-                        rot,
+                        id: inst.id,
+                        name: inst.model_name,
+                        pos: to_xzy(inst.pos),
+                        scale: inst.scale,
+                        rot: Quat::from_array([x, -z, -y, w]).normalize(),
                         handle: None,
                     })
                 }
-
-                "zone" => {}
-
-                "cull" => {}
-
-                "pick" => {}
-
-                "path" if ty == "ipl" => {}
-
-                "occl" => {}
-
-                "" => {
-                    error!("Line {} found outside of a section", linecount)
-                }
-
-                s => {
-                    error!(
-                        "Unknown section {} found in file {} at line {}, ignoring",
-                        s,
-                        &path.display(),
-                        linecount
-                    );
-                }
+                DefRecord::Zone(zone) => areas.zones.add(zone),
+                DefRecord::Cull(cull) => areas.cull.add(cull),
+                DefRecord::Occluder(occluder) => areas.occluders.add(occluder),
+                _ => {}
             }
         }
         Ok(())
     }
 
-    pub fn load_colfile(&mut self, path: &str) -> Result {
-        let path = get_path(&to_path(path)).ok_or(format!("{} not found!", path))?;
-        let colfile = std::fs::read(path)?;
-        let (_, cols) = many0(CollV1::parse)(&colfile).map_err(|err| err.to_owned())?;
-        for col in cols {
-            let old = self.col.insert(col.model_name.clone(), col);
-            assert!(old.is_none());
-        }
-
-        Ok(())
+    pub fn load_colfile(&mut self, path: &str, server: &AssetServer) {
+        let path = to_path(path).to_string_lossy().to_ascii_lowercase();
+        self.col.push(server.load(path));
     }
 
-    pub fn load_water(&mut self) -> Result {
-        let mut dat = Cursor::new(std::fs::read(GTA_DIR.join("data/waterpro.dat"))?);
+    pub fn load_water(&mut self, install: &GameInstall) -> Result {
+        let file = install.water_file();
+        let path = install
+            .get_path(Path::new(file))
+            .ok_or(format!("{file} not found!"))?;
+        let mut dat = Cursor::new(std::fs::read(path)?);
         let num_levels: u32 = dat.read_le()?;
         let mut heights: Vec<f32> = Vec::with_capacity(num_levels as usize);
         for _ in 0..num_levels {
@@ -248,7 +191,7 @@ impl Default for GameData {
     fn default() -> Self {
         Self {
             ide: Default::default(),
-            col: HashMap::new(),
+            col: Vec::new(),
             water_level: [f32::NEG_INFINITY; 128 * 128],
         }
     }
@@ -257,9 +200,49 @@ impl Default for GameData {
 #[derive(Default, Debug)]
 pub struct Ide {
     objs: HashMap<u32, IdeObj>,
+    vehicles: HashMap<u32, VehicleDef>,
+    peds: HashMap<u32, PedDef>,
+    /// 2dfx effects by model id
+    effects: HashMap<u32, Vec<Effect2d>>,
+    /// Ped and car path groups by model id
+    paths: HashMap<u32, Vec<PathGroup>>,
+    /// Parent TXD of every TXD, from the txdp section
+    txd_parents: HashMap<String, String>,
 }
 
 impl Ide {
+    // Stores the records that define models, every other record is handed back
+    pub fn add(&mut self, record: DefRecord) -> Option<DefRecord> {
+        match record {
+            DefRecord::Obj(obj) => {
+                self.objs.insert(obj.id, obj);
+            }
+            DefRecord::Vehicle(vehicle) => {
+                self.vehicles.insert(vehicle.id, vehicle);
+            }
+            DefRecord::Ped(ped) => {
+                self.peds.insert(ped.id, ped);
+            }
+            DefRecord::Effect(effect) => {
+                self.effects
+                    .entry(effect.model_id)
+                    .or_default()
+                    .push(effect);
+            }
+            // Groups of a model are added with each instance of it
+            DefRecord::Path(group) => match group.model {
+                Some((id, _)) => self.paths.entry(id).or_default().push(group),
+                None => return Some(DefRecord::Path(group)),
+            },
+            DefRecord::TxdParent { txd, parent } => {
+                self.txd_parents
+                    .insert(txd.to_lowercase(), parent.to_lowercase());
+            }
+            record => return Some(record),
+        }
+        None
+    }
+
     pub fn get_by_id(&self, id: u32) -> Option<&IdeObj> {
         self.objs.get(&id)
     }
@@ -269,14 +252,58 @@ impl Ide {
             .values()
             .find(|&obj| obj.model_name.to_lowercase() == name.to_lowercase())
     }
-}
 
-#[derive(Debug)]
-pub struct IdeObj {
-    pub id: u32,
-    pub model_name: String,
-    pub txd_name: String,
-    pub mesh_count: u32,
-    pub draw_distance: [f32; 3],
-    pub flags: u32,
+    pub fn get_vehicle(&self, id: u32) -> Option<&VehicleDef> {
+        self.vehicles.get(&id)
+    }
+
+    pub fn get_vehicle_by_model_name(&self, name: &str) -> Option<&VehicleDef> {
+        self.vehicles
+            .values()
+            .find(|v| v.model_name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn vehicles(&self) -> impl Iterator<Item = &VehicleDef> {
+        self.vehicles.values()
+    }
+
+    pub fn get_ped(&self, id: u32) -> Option<&PedDef> {
+        self.peds.get(&id)
+    }
+
+    pub fn get_ped_by_model_name(&self, name: &str) -> Option<&PedDef> {
+        self.peds
+            .values()
+            .find(|p| p.model_name.eq_ignore_ascii_case(name))
+    }
+
+    // Peds of a type from ped.dat, for picking a random model to spawn
+    pub fn peds_of_type<'a>(&'a self, ped_type: &'a str) -> impl Iterator<Item = &'a PedDef> {
+        self.peds
+            .values()
+            .filter(move |p| p.ped_type.eq_ignore_ascii_case(ped_type))
+    }
+
+    pub fn get_effects(&self, id: u32) -> &[Effect2d] {
+        self.effects.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn get_paths(&self, id: u32) -> &[PathGroup] {
+        self.paths.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    // All parents of a TXD, nearest first
+    pub fn get_txd_parents(&self, txd_name: &str) -> Vec<String> {
+        let mut parents = Vec::new();
+        let mut current = txd_name.to_lowercase();
+        while let Some(parent) = self.txd_parents.get(&current) {
+            if *parent == txd_name.to_lowercase() || parents.contains(parent) {
+                warn!("TXD parents of {} form a cycle", txd_name);
+                break;
+            }
+            parents.push(parent.clone());
+            current = parent.clone();
+        }
+        parents
+    }
 }
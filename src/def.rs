@@ -0,0 +1,1085 @@
+use std::str::FromStr;
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Which kind of definition file is parsed, some section names mean different things in each
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefKind {
+    Ide,
+    /// IPL files and the zone files referenced with mapzone
+    Ipl,
+}
+
+/// How the lines of an inst section are laid out, this differs between the games
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InstLayout {
+    #[default]
+    Gta3,
+    /// Vice City adds the interior the object is in after the model name
+    ViceCity,
+}
+
+const IDE_SECTIONS: [&str; 10] = [
+    "objs", "tobj", "hier", "cars", "peds", "path", "2dfx", "weap", "anim", "txdp",
+];
+const IPL_SECTIONS: [&str; 6] = ["inst", "zone", "cull", "pick", "path", "occl"];
+
+/// An object definition from the objs or tobj section of an IDE file
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdeObj {
+    pub id: u32,
+    pub model_name: String,
+    pub txd_name: String,
+    pub mesh_count: u32,
+    pub draw_distance: [f32; 3],
+    pub flags: ObjectFlags,
+    /// Hours the object is visible in, only set for tobj entries
+    pub time: Option<ObjTime>,
+}
+
+bitflags! {
+    /// Flags of an objs or tobj entry, unknown bits are kept as they are
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub struct ObjectFlags: u32 {
+        /// Darkened when it rains, used for roads
+        const WET = 1 << 0;
+        /// Pops in instead of fading in, set on most night and day variants of timed objects
+        const NO_FADE = 1 << 1;
+        /// Has transparent parts and is drawn after the opaque objects
+        const DRAW_LAST = 1 << 2;
+        /// Blended additively, for lights and glows. Only used together with DRAW_LAST.
+        const ADDITIVE = 1 << 3;
+        /// Part of a tunnel or the subway
+        const SUBWAY = 1 << 4;
+        /// Isn't affected by the ambient light, only its prelit vertex colors are used
+        const IGNORE_LIGHTING = 1 << 5;
+        /// Doesn't write to the depth buffer
+        const NO_ZBUFFER_WRITE = 1 << 6;
+        /// Doesn't receive shadows, Vice City and later
+        const NO_SHADOWS = 1 << 7;
+        /// Drawn without backface culling, San Andreas
+        const DISABLE_BACKFACE_CULLING = 1 << 21;
+    }
+}
+
+/// The in-game hours a timed object is visible in. When on is after off the range wraps around
+/// midnight, like the lights of night-only buildings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjTime {
+    pub on: u8,
+    pub off: u8,
+}
+
+impl ObjTime {
+    pub fn is_visible(self, hour: u8) -> bool {
+        if self.on > self.off {
+            hour >= self.on || hour < self.off
+        } else {
+            hour >= self.on && hour < self.off
+        }
+    }
+}
+
+/// A placed object from the inst section of an IPL file, in GTA coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub struct IplInst {
+    pub id: u32,
+    pub model_name: String,
+    /// Interior the object is in, always 0 in GTA III
+    pub interior: u32,
+    pub pos: [f32; 3],
+    pub scale: [f32; 3],
+    /// Rotation quaternion as x, y, z, w
+    pub rot: [f32; 4],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VehicleType {
+    Car,
+    Boat,
+    Train,
+    Heli,
+    Plane,
+}
+
+/// Wheels of a car, they are a separate model that is placed at each wheel dummy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VehicleWheels {
+    pub model_id: i32,
+    pub scale: f32,
+}
+
+/// A vehicle definition from the cars section of an IDE file
+#[derive(Clone, Debug, PartialEq)]
+pub struct VehicleDef {
+    pub id: u32,
+    pub model_name: String,
+    pub txd_name: String,
+    pub vehicle_type: VehicleType,
+    /// Entry in handling.cfg
+    pub handling_id: String,
+    /// Key of the vehicle name in the GXT
+    pub game_name: String,
+    /// Class used to pick random traffic, like poorfamily or executive
+    pub class: String,
+    /// How often the vehicle appears in traffic
+    pub frequency: u32,
+    pub level: u32,
+    /// Rules for which extra components get enabled, packed as hex digits
+    pub comp_rules: u32,
+    /// Only set for cars
+    pub wheels: Option<VehicleWheels>,
+    /// Model shown from far away, only set for planes
+    pub lod_model_id: Option<i32>,
+}
+
+/// A pedestrian definition from the peds section of an IDE file. The ped type and stat names
+/// are the keys of the entries in ped.dat and pedstats.dat.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PedDef {
+    pub id: u32,
+    pub model_name: String,
+    pub txd_name: String,
+    /// Ped type from ped.dat, like CIVMALE or COP
+    pub ped_type: String,
+    /// Behaviour from pedstats.dat, like STAT_STREET_GUY
+    pub stat_name: String,
+    /// Animation group, like man or woman
+    pub anim_group: String,
+    /// Classes of cars the ped drives, one bit per class
+    pub car_driving_mask: u32,
+}
+
+/// An effect attached to a model from the 2dfx section of an IDE file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Effect2d {
+    pub model_id: u32,
+    /// Position relative to the model, in GTA coordinates
+    pub pos: [f32; 3],
+    pub color: [u8; 4],
+    pub kind: Effect2dKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Effect2dKind {
+    Light(LightEffect),
+    Particle {
+        particle_type: u32,
+        direction: [f32; 3],
+        scale: f32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightEffect {
+    /// Raster in particle.txd drawn as the corona
+    pub corona_texture: String,
+    /// Raster in particle.txd projected onto the ground below the light
+    pub shadow_texture: String,
+    /// Distance the corona is visible from
+    pub distance: f32,
+    /// Range of the light itself
+    pub outer_range: f32,
+    pub corona_size: f32,
+    /// Size of the shadow texture
+    pub inner_range: f32,
+    pub shadow_intensity: u8,
+    /// How the light blinks, 0 is constant
+    pub flash: u8,
+    /// Whether the light is reflected by wet roads
+    pub wet_reflection: bool,
+    /// Lens flare, 0 is none
+    pub flare: u8,
+    pub flags: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PathType {
+    Ped,
+    Car,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathNodeType {
+    /// Unused slot of the group
+    None,
+    /// Node at the edge of the model that connects to the paths of neighbouring models
+    External,
+    Internal,
+}
+
+/// A node of a path group
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathNode {
+    pub node_type: PathNodeType,
+    /// Index of the node in the group this one links to, -1 for none
+    pub next: i32,
+    pub cross_road: bool,
+    /// Position in GTA coordinates, relative to the model or in the world for groups without one.
+    /// IDE files store them in sixteenths of a unit.
+    pub pos: [f32; 3],
+    /// Width of the median between the lanes of both directions
+    pub median: f32,
+    pub left_lanes: u8,
+    pub right_lanes: u8,
+}
+
+/// A group of 12 path nodes from a path section. Groups in IDE files are attached to a model and
+/// placed with each instance of it, the ones in Vice City IPL files are placed in the world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathGroup {
+    pub path_type: PathType,
+    /// Id and name of the model the group is attached to, None for IPL groups
+    pub model: Option<(u32, String)>,
+    pub nodes: Vec<PathNode>,
+}
+
+const PATH_GROUP_NODES: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZoneType {
+    /// Zones covering whole areas, only used for their names
+    Default,
+    /// Zones whose names are shown when entering them
+    Navigation,
+    /// Zones with the population and gang settings of an area
+    Info,
+    /// Zones from the mapzone files, used by the radar
+    Map,
+}
+
+/// The island a zone is on, 0 is none
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IslandLevel {
+    None,
+    Industrial,
+    Commercial,
+    Suburban,
+}
+
+/// A named box from the zone section of an IPL file, in GTA coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub struct IplZone {
+    pub name: String,
+    pub zone_type: ZoneType,
+    /// Corners of the box, the files don't always list the smaller corner first
+    pub corners: [[f32; 3]; 2],
+    pub level: IslandLevel,
+}
+
+bitflags! {
+    /// Attributes of a cull zone
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    pub struct ZoneAttributes: u32 {
+        /// The camera moves closer to the player, used in narrow places
+        const CAM_CLOSE_IN = 1 << 0;
+        const STAIRS = 1 << 1;
+        /// The camera switches to first person
+        const FIRST_PERSON = 1 << 2;
+        /// Covered areas where it doesn't rain
+        const NO_RAIN = 1 << 3;
+        /// Police doesn't follow the player in here
+        const NO_POLICE = 1 << 4;
+        /// The zone only sets attributes and doesn't cull anything
+        const NOT_CULL_ZONE = 1 << 5;
+        /// Collision has to be loaded even when the player isn't close
+        const NEEDS_COLLISION = 1 << 6;
+        /// The subway tunnels are drawn from inside the zone
+        const SUBWAY_VISIBLE = 1 << 7;
+    }
+}
+
+/// A box from the cull section of an IPL file, in GTA coordinates
+#[derive(Clone, Debug, PartialEq)]
+pub struct IplCull {
+    pub center: [f32; 3],
+    /// Corners of the box, the files don't always list the smaller corner first
+    pub corners: [[f32; 3]; 2],
+    pub attributes: ZoneAttributes,
+    /// How many wanted stars the player loses inside the zone
+    pub wanted_level_drop: u32,
+}
+
+/// A box from the occl section of an IPL file, objects behind it aren't drawn. GTA III doesn't
+/// use these, they were added in Vice City.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IplOccluder {
+    /// Center of the bottom face in GTA coordinates
+    pub pos: [f32; 3],
+    pub width: f32,
+    pub length: f32,
+    pub height: f32,
+    /// Rotation around the vertical axis in degrees
+    pub angle: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DefRecord {
+    Obj(IdeObj),
+    Vehicle(VehicleDef),
+    Ped(PedDef),
+    Effect(Effect2d),
+    /// Entry of the txdp section, the parent is searched for textures missing from the TXD
+    TxdParent {
+        txd: String,
+        parent: String,
+    },
+    Path(PathGroup),
+    Inst(IplInst),
+    Zone(IplZone),
+    Cull(IplCull),
+    Occluder(IplOccluder),
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+#[error("{file}:{line}:{column}: {kind}")]
+pub struct DefParseError {
+    pub file: String,
+    pub line: usize,
+    /// Column of the offending field in characters, starting at 1
+    pub column: usize,
+    pub kind: DefErrorKind,
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum DefErrorKind {
+    #[error("unknown section {0}")]
+    UnknownSection(String),
+    #[error("line outside of a section")]
+    OutsideSection,
+    #[error("{section} entry has {found} fields, expected {expected}")]
+    FieldCount {
+        section: &'static str,
+        expected: &'static str,
+        found: usize,
+    },
+    #[error("invalid {field} {value:?}")]
+    InvalidField { field: &'static str, value: String },
+    #[error("path group has {found} nodes, expected 12")]
+    IncompletePath { found: usize },
+    #[error("path node before the header of its group")]
+    PathNodeOutsideGroup,
+}
+
+/// Everything that could be read from a definition file. Lines with errors are skipped,
+/// the rest of the file is still parsed.
+#[derive(Default, Debug)]
+pub struct DefFile {
+    /// Records with the line they were found on
+    pub records: Vec<(usize, DefRecord)>,
+    pub errors: Vec<DefParseError>,
+}
+
+/// A field of a line and the column it starts at
+struct Field<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+/// A non-empty line split into fields
+struct Line<'a> {
+    file: &'a str,
+    number: usize,
+    fields: Vec<Field<'a>>,
+}
+
+impl<'a> Line<'a> {
+    // Splits a line at commas and whitespace, everything after a # is a comment
+    fn split(file: &'a str, number: usize, text: &'a str) -> Self {
+        let text = text.split('#').next().unwrap_or_default();
+        let mut fields = Vec::new();
+        let mut start = None;
+        for (column, (i, c)) in text.char_indices().enumerate() {
+            let separator = c == ',' || c.is_whitespace();
+            match (start, separator) {
+                (None, false) => start = Some((i, column)),
+                (Some((s, start_column)), true) => {
+                    fields.push(Field {
+                        text: &text[s..i],
+                        column: start_column + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some((s, start_column)) = start {
+            fields.push(Field {
+                text: &text[s..],
+                column: start_column + 1,
+            });
+        }
+        Self {
+            file,
+            number,
+            fields,
+        }
+    }
+
+    fn error(&self, column: usize, kind: DefErrorKind) -> DefParseError {
+        DefParseError {
+            file: self.file.to_owned(),
+            line: self.number,
+            column,
+            kind,
+        }
+    }
+
+    // Checks the number of fields, reporting the error at the first missing or extra field
+    fn expect_fields(
+        &self,
+        section: &'static str,
+        expected: &'static str,
+        valid: impl Fn(usize) -> bool,
+    ) -> Result<(), DefParseError> {
+        let found = self.fields.len();
+        if valid(found) {
+            return Ok(());
+        }
+        let column = self
+            .fields
+            .last()
+            .map_or(1, |f| f.column + f.text.chars().count());
+        Err(self.error(
+            column,
+            DefErrorKind::FieldCount {
+                section,
+                expected,
+                found,
+            },
+        ))
+    }
+
+    // Texture names in 2dfx entries are quoted
+    fn str(&self, i: usize) -> String {
+        self.fields[i].text.trim_matches('"').to_owned()
+    }
+
+    // Hex numbers are written without a prefix in the IDE files, but accept one anyway
+    fn parse_hex(&self, i: usize, field: &'static str) -> Result<u32, DefParseError> {
+        let f = &self.fields[i];
+        let digits = f.text.trim_start_matches("0x").trim_start_matches("0X");
+        u32::from_str_radix(digits, 16).map_err(|_| {
+            self.error(
+                f.column,
+                DefErrorKind::InvalidField {
+                    field,
+                    value: f.text.to_owned(),
+                },
+            )
+        })
+    }
+
+    fn parse<T: FromStr>(&self, i: usize, field: &'static str) -> Result<T, DefParseError> {
+        let f = &self.fields[i];
+        f.text.parse().map_err(|_| {
+            self.error(
+                f.column,
+                DefErrorKind::InvalidField {
+                    field,
+                    value: f.text.to_owned(),
+                },
+            )
+        })
+    }
+}
+
+// Parses an IDE or IPL file, file is only used for error messages
+pub fn parse_def(kind: DefKind, layout: InstLayout, file: &str, text: &str) -> DefFile {
+    let sections: &[&str] = match kind {
+        DefKind::Ide => &IDE_SECTIONS,
+        DefKind::Ipl => &IPL_SECTIONS,
+    };
+    let mut result = DefFile::default();
+    // None outside of sections, Some("") inside an unknown section whose lines we skip
+    let mut section: Option<&str> = None;
+    // Path group whose nodes are still being read, with the line of its header
+    let mut path: Option<(usize, PathGroup)> = None;
+    // str::lines also strips the \r of CRLF line endings
+    for (i, text) in text.lines().enumerate() {
+        let line = Line::split(file, i + 1, text);
+        if line.fields.is_empty() {
+            continue;
+        }
+
+        // Inside a section only end is a header, other lines with a single field are entries
+        // missing their other fields
+        let is_end = line.fields.len() == 1 && line.fields[0].text.eq_ignore_ascii_case("end");
+        if is_end || (section.is_none() && line.fields.len() == 1) {
+            if let Some((number, group)) = path.take() {
+                result.errors.push(incomplete_path(file, number, &group));
+            }
+            let name = line.fields[0].text.to_ascii_lowercase();
+            if is_end {
+                section = None;
+            } else if let Some(&s) = sections.iter().find(|&&s| s == name) {
+                section = Some(s);
+            } else {
+                result.errors.push(line.error(
+                    line.fields[0].column,
+                    DefErrorKind::UnknownSection(line.str(0)),
+                ));
+                section = Some("");
+            }
+            continue;
+        }
+
+        let record = match section {
+            None => Err(line.error(line.fields[0].column, DefErrorKind::OutsideSection)),
+            Some("objs") => parse_obj(&line, false).map(Some),
+            Some("tobj") => parse_obj(&line, true).map(Some),
+            Some("txdp") => line.expect_fields("txdp", "2", |n| n == 2).map(|()| {
+                Some(DefRecord::TxdParent {
+                    txd: line.str(0),
+                    parent: line.str(1),
+                })
+            }),
+            Some("cars") => parse_car(&line).map(Some),
+            Some("peds") => parse_ped(&line).map(Some),
+            Some("2dfx") => parse_2dfx(&line),
+            Some("path") => parse_path(&line, kind, &mut path),
+            Some("inst") => parse_inst(&line, layout).map(Some),
+            Some("zone") => parse_zone(&line).map(Some),
+            Some("cull") => parse_cull(&line).map(Some),
+            Some("occl") => parse_occluder(&line).map(Some),
+            // Sections we don't use yet
+            Some(_) => Ok(None),
+        };
+        match record {
+            Ok(Some(record)) => result.records.push((line.number, record)),
+            Ok(None) => {}
+            Err(e) => result.errors.push(e),
+        }
+    }
+    if let Some((number, group)) = path {
+        result.errors.push(incomplete_path(file, number, &group));
+    }
+    result
+}
+
+fn incomplete_path(file: &str, line: usize, group: &PathGroup) -> DefParseError {
+    DefParseError {
+        file: file.to_owned(),
+        line,
+        column: 1,
+        kind: DefErrorKind::IncompletePath {
+            found: group.nodes.len(),
+        },
+    }
+}
+
+// objs lines either have a single draw distance, or a mesh count followed by one draw
+// distance per mesh. tobj lines have the hours the object is visible in at the end.
+fn parse_obj(line: &Line, timed: bool) -> Result<DefRecord, DefParseError> {
+    let (section, extra) = if timed { ("tobj", 2) } else { ("objs", 0) };
+    line.expect_fields(section, if timed { "7 to 10" } else { "5 to 8" }, |n| {
+        (5 + extra..=8 + extra).contains(&n)
+    })?;
+    let num_fields = line.fields.len() - extra;
+
+    let mut obj = IdeObj {
+        id: line.parse(0, "id")?,
+        model_name: line.str(1),
+        txd_name: line.str(2),
+        mesh_count: 1,
+        draw_distance: [0.0; 3],
+        flags: ObjectFlags::empty(),
+        time: None,
+    };
+    let distances = if num_fields == 5 {
+        3..4
+    } else {
+        obj.mesh_count = (num_fields - 5) as u32;
+        4..num_fields - 1
+    };
+    for (i, field) in distances.enumerate() {
+        obj.draw_distance[i] = line.parse(field, "draw distance")?;
+    }
+    obj.flags = ObjectFlags::from_bits_retain(line.parse(num_fields - 1, "flags")?);
+    if timed {
+        let hour = |i: usize, field: &'static str| {
+            line.parse::<u8>(i, field).and_then(|h| match h {
+                0..=24 => Ok(h),
+                _ => Err(line.error(
+                    line.fields[i].column,
+                    DefErrorKind::InvalidField {
+                        field,
+                        value: line.str(i),
+                    },
+                )),
+            })
+        };
+        obj.time = Some(ObjTime {
+            on: hour(num_fields, "time on")?,
+            off: hour(num_fields + 1, "time off")?,
+        });
+    }
+    Ok(DefRecord::Obj(obj))
+}
+
+// Every vehicle has the same ten fields, cars add their wheels and planes their LOD model
+fn parse_car(line: &Line) -> Result<DefRecord, DefParseError> {
+    line.expect_fields("cars", "10 to 12", |n| (10..=12).contains(&n))?;
+    let vehicle_type = match line.fields[3].text.to_ascii_lowercase().as_str() {
+        "car" => VehicleType::Car,
+        "boat" => VehicleType::Boat,
+        "train" => VehicleType::Train,
+        "heli" => VehicleType::Heli,
+        "plane" => VehicleType::Plane,
+        _ => {
+            return Err(line.error(
+                line.fields[3].column,
+                DefErrorKind::InvalidField {
+                    field: "vehicle type",
+                    value: line.str(3),
+                },
+            ))
+        }
+    };
+    let (expected, count) = match vehicle_type {
+        VehicleType::Car => ("12", 12),
+        VehicleType::Plane => ("11", 11),
+        _ => ("10", 10),
+    };
+    line.expect_fields("cars", expected, |n| n == count)?;
+
+    let comp_rules = line.parse_hex(9, "comp rules")?;
+    Ok(DefRecord::Vehicle(VehicleDef {
+        id: line.parse(0, "id")?,
+        model_name: line.str(1),
+        txd_name: line.str(2),
+        vehicle_type,
+        handling_id: line.str(4),
+        game_name: line.str(5),
+        class: line.str(6),
+        frequency: line.parse(7, "frequency")?,
+        level: line.parse(8, "level")?,
+        comp_rules,
+        wheels: match vehicle_type {
+            VehicleType::Car => Some(VehicleWheels {
+                model_id: line.parse(10, "wheel model id")?,
+                scale: line.parse(11, "wheel scale")?,
+            }),
+            _ => None,
+        },
+        lod_model_id: match vehicle_type {
+            VehicleType::Plane => Some(line.parse(10, "LOD model id")?),
+            _ => None,
+        },
+    }))
+}
+
+fn parse_ped(line: &Line) -> Result<DefRecord, DefParseError> {
+    line.expect_fields("peds", "7", |n| n == 7)?;
+    Ok(DefRecord::Ped(PedDef {
+        id: line.parse(0, "id")?,
+        model_name: line.str(1),
+        txd_name: line.str(2),
+        ped_type: line.str(3),
+        stat_name: line.str(4),
+        anim_group: line.str(5),
+        car_driving_mask: line.parse_hex(6, "car driving mask")?,
+    }))
+}
+
+// Every effect starts with the model, position, color and type. Lights and particles are the only
+// types GTA III uses, other types are skipped.
+fn parse_2dfx(line: &Line) -> Result<Option<DefRecord>, DefParseError> {
+    line.expect_fields("2dfx", "at least 9", |n| n >= 9)?;
+    let f = |i: usize, field: &'static str| line.parse::<f32>(i, field);
+    let kind = match line.parse::<u32>(8, "effect type")? {
+        0 => {
+            line.expect_fields("2dfx", "20", |n| n == 20)?;
+            Effect2dKind::Light(LightEffect {
+                corona_texture: line.str(9),
+                shadow_texture: line.str(10),
+                distance: f(11, "distance")?,
+                outer_range: f(12, "outer range")?,
+                corona_size: f(13, "corona size")?,
+                inner_range: f(14, "inner range")?,
+                shadow_intensity: line.parse(15, "shadow intensity")?,
+                flash: line.parse(16, "flash")?,
+                wet_reflection: line.parse::<u8>(17, "wet reflection")? != 0,
+                flare: line.parse(18, "flare")?,
+                flags: line.parse(19, "flags")?,
+            })
+        }
+        1 => {
+            line.expect_fields("2dfx", "14", |n| n == 14)?;
+            Effect2dKind::Particle {
+                particle_type: line.parse(9, "particle type")?,
+                direction: [
+                    f(10, "direction")?,
+                    f(11, "direction")?,
+                    f(12, "direction")?,
+                ],
+                scale: f(13, "scale")?,
+            }
+        }
+        _ => return Ok(None),
+    };
+    let color = |i: usize| line.parse::<u8>(i, "color");
+    Ok(Some(DefRecord::Effect(Effect2d {
+        model_id: line.parse(0, "model id")?,
+        pos: [f(1, "position")?, f(2, "position")?, f(3, "position")?],
+        color: [color(4)?, color(5)?, color(6)?, color(7)?],
+        kind,
+    })))
+}
+
+// A path group is a header with the path type and model followed by one line per node, the
+// group is returned once all of its nodes are read. IPL headers have -1 instead of a model and
+// their nodes are in world units. Vice City adds the speed limit, flags and spawn rate to the
+// nodes of IPL groups, these aren't used.
+fn parse_path(
+    line: &Line,
+    kind: DefKind,
+    group: &mut Option<(usize, PathGroup)>,
+) -> Result<Option<DefRecord>, DefParseError> {
+    let ipl = kind == DefKind::Ipl;
+    let (expected, header_fields) = if ipl {
+        ("2 or 9 to 12", 2)
+    } else {
+        ("3 or 9", 3)
+    };
+    line.expect_fields("path", expected, |n| {
+        n == header_fields || n == 9 || (ipl && (9..=12).contains(&n))
+    })?;
+    if line.fields.len() == header_fields {
+        let path_type = match line.fields[0].text.to_ascii_lowercase().as_str() {
+            "ped" => PathType::Ped,
+            "car" => PathType::Car,
+            _ => {
+                return Err(line.error(
+                    line.fields[0].column,
+                    DefErrorKind::InvalidField {
+                        field: "path type",
+                        value: line.str(0),
+                    },
+                ))
+            }
+        };
+        let model = if ipl {
+            if line.parse::<i32>(1, "model id")? != -1 {
+                return Err(line.error(
+                    line.fields[1].column,
+                    DefErrorKind::InvalidField {
+                        field: "model id",
+                        value: line.str(1),
+                    },
+                ));
+            }
+            None
+        } else {
+            Some((line.parse(1, "model id")?, line.str(2)))
+        };
+        let previous = group.replace((
+            line.number,
+            PathGroup {
+                path_type,
+                model,
+                nodes: Vec::with_capacity(PATH_GROUP_NODES),
+            },
+        ));
+        return match previous {
+            Some((number, previous)) => Err(incomplete_path(line.file, number, &previous)),
+            None => Ok(None),
+        };
+    }
+
+    let Some((_, current)) = group else {
+        return Err(line.error(line.fields[0].column, DefErrorKind::PathNodeOutsideGroup));
+    };
+    let node_type = match line.parse::<u8>(0, "node type")? {
+        0 => PathNodeType::None,
+        1 => PathNodeType::External,
+        2 => PathNodeType::Internal,
+        _ => {
+            return Err(line.error(
+                line.fields[0].column,
+                DefErrorKind::InvalidField {
+                    field: "node type",
+                    value: line.str(0),
+                },
+            ))
+        }
+    };
+    let scale = if ipl { 1.0 } else { 16.0 };
+    let f = |i: usize, field: &'static str| line.parse::<f32>(i, field).map(|v| v / scale);
+    current.nodes.push(PathNode {
+        node_type,
+        next: line.parse(1, "next node")?,
+        cross_road: line.parse::<u8>(2, "cross road")? != 0,
+        pos: [f(3, "position")?, f(4, "position")?, f(5, "position")?],
+        median: f(6, "median")?,
+        left_lanes: line.parse(7, "left lanes")?,
+        right_lanes: line.parse(8, "right lanes")?,
+    });
+    if current.nodes.len() < PATH_GROUP_NODES {
+        return Ok(None);
+    }
+    Ok(group.take().map(|(_, group)| DefRecord::Path(group)))
+}
+
+fn parse_inst(line: &Line, layout: InstLayout) -> Result<DefRecord, DefParseError> {
+    let (interior, first) = match layout {
+        InstLayout::Gta3 => {
+            line.expect_fields("inst", "12", |n| n == 12)?;
+            (0, 2)
+        }
+        InstLayout::ViceCity => {
+            line.expect_fields("inst", "13", |n| n == 13)?;
+            (line.parse(2, "interior")?, 3)
+        }
+    };
+    let f = |i: usize, field: &'static str| line.parse::<f32>(first + i, field);
+    Ok(DefRecord::Inst(IplInst {
+        id: line.parse(0, "id")?,
+        model_name: line.str(1),
+        interior,
+        pos: [f(0, "position")?, f(1, "position")?, f(2, "position")?],
+        scale: [f(3, "scale")?, f(4, "scale")?, f(5, "scale")?],
+        rot: [
+            f(6, "rotation")?,
+            f(7, "rotation")?,
+            f(8, "rotation")?,
+            f(9, "rotation")?,
+        ],
+    }))
+}
+
+fn parse_zone(line: &Line) -> Result<DefRecord, DefParseError> {
+    line.expect_fields("zone", "9", |n| n == 9)?;
+    let f = |i: usize| line.parse::<f32>(i, "corner");
+    let invalid = |i: usize, field: &'static str| {
+        line.error(
+            line.fields[i].column,
+            DefErrorKind::InvalidField {
+                field,
+                value: line.str(i),
+            },
+        )
+    };
+    let zone_type = match line.parse::<u8>(1, "zone type")? {
+        0 => ZoneType::Default,
+        1 => ZoneType::Navigation,
+        2 => ZoneType::Info,
+        3 => ZoneType::Map,
+        _ => return Err(invalid(1, "zone type")),
+    };
+    let level = match line.parse::<u8>(8, "level")? {
+        0 => IslandLevel::None,
+        1 => IslandLevel::Industrial,
+        2 => IslandLevel::Commercial,
+        3 => IslandLevel::Suburban,
+        _ => return Err(invalid(8, "level")),
+    };
+    Ok(DefRecord::Zone(IplZone {
+        name: line.str(0),
+        zone_type,
+        corners: [[f(2)?, f(3)?, f(4)?], [f(5)?, f(6)?, f(7)?]],
+        level,
+    }))
+}
+
+fn parse_cull(line: &Line) -> Result<DefRecord, DefParseError> {
+    line.expect_fields("cull", "11", |n| n == 11)?;
+    let f = |i: usize, field: &'static str| line.parse::<f32>(i, field);
+    Ok(DefRecord::Cull(IplCull {
+        center: [f(0, "center")?, f(1, "center")?, f(2, "center")?],
+        corners: [
+            [f(3, "corner")?, f(4, "corner")?, f(5, "corner")?],
+            [f(6, "corner")?, f(7, "corner")?, f(8, "corner")?],
+        ],
+        attributes: ZoneAttributes::from_bits_retain(line.parse(9, "attributes")?),
+        wanted_level_drop: line.parse(10, "wanted level drop")?,
+    }))
+}
+
+fn parse_occluder(line: &Line) -> Result<DefRecord, DefParseError> {
+    line.expect_fields("occl", "7", |n| n == 7)?;
+    let f = |i: usize, field: &'static str| line.parse::<f32>(i, field);
+    Ok(DefRecord::Occluder(IplOccluder {
+        pos: [f(0, "position")?, f(1, "position")?, f(2, "position")?],
+        width: f(3, "width")?,
+        length: f(4, "length")?,
+        height: f(5, "height")?,
+        angle: f(6, "angle")?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obj(draw_distance: f32) -> DefRecord {
+        DefRecord::Obj(IdeObj {
+            id: 1,
+            model_name: "bar".to_owned(),
+            txd_name: "bartex".to_owned(),
+            mesh_count: 1,
+            draw_distance: [draw_distance, 0.0, 0.0],
+            flags: ObjectFlags::empty(),
+            time: None,
+        })
+    }
+
+    // The line and column of the only error in an IDE file
+    fn error_at(text: &str) -> (usize, usize, DefErrorKind) {
+        let def = parse_def(DefKind::Ide, InstLayout::Gta3, "test.ide", text);
+        assert!(def.records.is_empty(), "{:?}", def.records);
+        match &def.errors[..] {
+            [e] => (e.line, e.column, e.kind.clone()),
+            errors => panic!("expected one error, got {errors:?}"),
+        }
+    }
+
+    #[test]
+    fn tabs_separate_fields() {
+        let def = parse_def(
+            DefKind::Ide,
+            "test.ide",
+            "objs\n1\tbar\t\tbartex,\t100\t0\nend\n",
+        );
+        assert!(def.errors.is_empty(), "{:?}", def.errors);
+        assert_eq!(def.records, [(2, obj(100.0))]);
+    }
+
+    #[test]
+    fn trailing_comments_are_ignored() {
+        let text = "objs # buildings\n1, bar, bartex, 100, 0 # extra, fields, 5\nend\n";
+        let def = parse_def(DefKind::Ide, InstLayout::Gta3, "test.ide", text);
+        assert!(def.errors.is_empty(), "{:?}", def.errors);
+        assert_eq!(def.records, [(2, obj(100.0))]);
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let def = parse_def(
+            DefKind::Ide,
+            "test.ide",
+            "objs\r\n1, bar, bartex, 100, 0\r\nend\r\n",
+        );
+        assert!(def.errors.is_empty(), "{:?}", def.errors);
+        assert_eq!(def.records, [(2, obj(100.0))]);
+
+        let (line, column, kind) = error_at("objs\r\n\r\n1, bar, bartex, far, 0\r\nend\r\n");
+        assert_eq!((line, column), (3, 17));
+        assert_eq!(
+            kind,
+            DefErrorKind::InvalidField {
+                field: "draw distance",
+                value: "far".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_reported_after_the_last_field() {
+        let (line, column, kind) = error_at("objs\n1, bar, bartex, 100\nend\n");
+        assert_eq!((line, column), (2, 20));
+        assert_eq!(
+            kind,
+            DefErrorKind::FieldCount {
+                section: "objs",
+                expected: "5 to 8",
+                found: 4
+            }
+        );
+    }
+
+    #[test]
+    fn columns_count_characters_not_bytes() {
+        // "größe" is 5 characters but 7 bytes
+        let (line, column, _) = error_at("objs\n1, bar, größe\nend\n");
+        assert_eq!((line, column), (2, 14));
+
+        let (line, column, kind) = error_at("objs\n1, größe, bartex, far, 0\nend\n");
+        assert_eq!((line, column), (2, 19));
+        assert_eq!(
+            kind,
+            DefErrorKind::InvalidField {
+                field: "draw distance",
+                value: "far".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn single_fields_inside_a_section_are_broken_entries() {
+        let text = "inst\n\
+            1, bar, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1\n\
+            2\n\
+            objs\n\
+            3, baz, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1\n\
+            end\n";
+        let def = parse_def(DefKind::Ipl, InstLayout::Gta3, "test.ipl", text);
+        let ids: Vec<_> = def
+            .records
+            .iter()
+            .map(|(line, record)| match record {
+                DefRecord::Inst(inst) => (*line, inst.id),
+                _ => panic!("unexpected record {record:?}"),
+            })
+            .collect();
+        assert_eq!(ids, [(2, 1), (5, 3)]);
+        let errors: Vec<_> = def
+            .errors
+            .iter()
+            .map(|e| (e.line, e.column, e.kind.clone()))
+            .collect();
+        let field_count = |found| DefErrorKind::FieldCount {
+            section: "inst",
+            expected: "12",
+            found,
+        };
+        assert_eq!(errors, [(3, 2, field_count(1)), (4, 5, field_count(1))]);
+    }
+
+    #[test]
+    fn vice_city_inst_lines_have_an_interior() {
+        let text = "inst\n100, bar, 3, 1, 2, 3, 1, 1, 1, 0, 0, 0, 1\nend\n";
+        let def = parse_def(DefKind::Ipl, InstLayout::ViceCity, "test.ipl", text);
+        assert!(def.errors.is_empty(), "{:?}", def.errors);
+        assert_eq!(
+            def.records,
+            [(
+                2,
+                DefRecord::Inst(IplInst {
+                    id: 100,
+                    model_name: "bar".to_owned(),
+                    interior: 3,
+                    pos: [1.0, 2.0, 3.0],
+                    scale: [1.0; 3],
+                    rot: [0.0, 0.0, 0.0, 1.0],
+                })
+            )]
+        );
+
+        // The GTA III layout has one field less
+        let def = parse_def(DefKind::Ipl, InstLayout::Gta3, "test.ipl", text);
+        assert!(def.records.is_empty());
+        assert_eq!(def.errors.len(), 1);
+    }
+
+    #[test]
+    fn ipl_path_groups_have_no_model() {
+        let mut text = "path\nped, -1\n".to_owned();
+        text += "1, -1, 0, 100.5, -200, 10, 2, 1, 1, 0, 0, 1\n";
+        text += &"0, -1, 0, 0, 0, 0, 0, 0, 0\n".repeat(11);
+        text += "end\n";
+        let def = parse_def(DefKind::Ipl, InstLayout::Gta3, "test.ipl", &text);
+        assert!(def.errors.is_empty(), "{:?}", def.errors);
+        let [(14, DefRecord::Path(group))] = &def.records[..] else {
+            panic!("expected one path group, got {:?}", def.records);
+        };
+        assert_eq!(group.path_type, PathType::Ped);
+        assert_eq!(group.model, None);
+        assert_eq!(group.nodes.len(), 12);
+        assert_eq!(group.nodes[0].node_type, PathNodeType::External);
+        assert_eq!(group.nodes[0].pos, [100.5, -200.0, 10.0]);
+        assert_eq!(group.nodes[0].median, 2.0);
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use nom_derive::{nom::multi::many0, Parse};
+use rw_rs::col::CollV1;
+
+use crate::{
+    archive::ImgArchives,
+    assets::TxdLoader,
+    def::{parse_def, DefKind, DefRecord},
+    install::GameInstall,
+    utils::{get_mod_path, to_path},
+};
+
+/// Kinds of problems the doctor reports, in the order they are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Category {
+    MissingFile,
+    CorruptFile,
+    UnknownObject,
+    MissingModel,
+    MissingTxd,
+    BadTexture,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Category::MissingFile => "Missing files",
+            Category::CorruptFile => "Corrupt files",
+            Category::UnknownObject => "IPL instances without IDE entry",
+            Category::MissingModel => "Missing models",
+            Category::MissingTxd => "Missing texture dictionaries",
+            Category::BadTexture => "Textures that fail to decode",
+        })
+    }
+}
+
+#[derive(Default)]
+struct Report(BTreeMap<Category, Vec<String>>);
+
+impl Report {
+    fn add(&mut self, category: Category, problem: String) {
+        self.0.entry(category).or_default().push(problem);
+    }
+
+    fn print(&self) {
+        for (category, problems) in &self.0 {
+            println!("{category} ({}):", problems.len());
+            for problem in problems {
+                println!("    {problem}");
+            }
+        }
+    }
+}
+
+/// What the doctor needs to know about an IDE object
+struct ObjDef {
+    model_name: String,
+    txd_name: String,
+    location: String,
+}
+
+/// Everything collected from the files listed in the dat file
+#[derive(Default)]
+struct Defs {
+    objs: HashMap<u32, ObjDef>,
+    /// IPL instances as (id, name, location)
+    instances: Vec<(u32, String, String)>,
+}
+
+// Checks the game in gta_dir and prints a report, returns whether no problems were found
+pub fn run(gta_dir: &Path, extra_imgs: &[PathBuf]) -> Result<bool> {
+    let install = GameInstall::detect(gta_dir)?;
+    println!(
+        "Found {} in {}",
+        install.version.name(),
+        install.root.display()
+    );
+    let mut report = Report::default();
+
+    let mut archives = match ImgArchives::new(&install.root.join(install.img_file())) {
+        Ok(archives) => archives,
+        Err(e) => {
+            report.add(
+                Category::CorruptFile,
+                format!("{}: {e}", install.img_file()),
+            );
+            report.print();
+            return Ok(false);
+        }
+    };
+
+    let mut defs = Defs::default();
+    match install.get_path(Path::new(install.dat_file())) {
+        Some(path) => {
+            for (line, words) in lines(&fs::read_to_string(&path)?) {
+                let location = format!("{}:{line}", install.dat_file());
+                match (words[0].to_lowercase().as_str(), words.get(1)) {
+                    ("ide", Some(file)) => {
+                        check_def(&install, DefKind::Ide, file, &mut defs, &mut report)
+                    }
+                    ("ipl" | "mapzone", Some(file)) => {
+                        check_def(&install, DefKind::Ipl, file, &mut defs, &mut report)
+                    }
+                    ("img" | "cdimage", Some(file)) => match install.get_path(&to_path(file)) {
+                        Some(path) => {
+                            if let Err(e) = archives.add_game(&path) {
+                                report.add(Category::CorruptFile, format!("{file}: {e}"));
+                            }
+                        }
+                        None => report.add(Category::MissingFile, format!("{file} ({location})")),
+                    },
+                    ("colfile", Some(_)) => match words.get(2) {
+                        Some(file) => check_col(&install, file, &location, &mut report),
+                        None => report.add(
+                            Category::CorruptFile,
+                            format!("{location}: colfile without path"),
+                        ),
+                    },
+                    _ => {}
+                }
+            }
+        }
+        None => report.add(Category::MissingFile, install.dat_file().to_owned()),
+    }
+    for img in extra_imgs {
+        if let Err(e) = archives.add(img) {
+            report.add(Category::CorruptFile, format!("{}: {e}", img.display()));
+        }
+    }
+
+    for (id, name, location) in &defs.instances {
+        if !defs.objs.contains_key(id) {
+            report.add(
+                Category::UnknownObject,
+                format!("{name} ({id}) at {location}"),
+            );
+        }
+    }
+
+    let mut ids: Vec<_> = defs.objs.keys().copied().collect();
+    ids.sort();
+    let mut txds = Vec::new();
+    let mut seen_txds = HashSet::new();
+    for id in ids {
+        let obj = &defs.objs[&id];
+        let model = format!("{}.dff", obj.model_name);
+        if !asset_exists(&install, &archives, &model) {
+            report.add(
+                Category::MissingModel,
+                format!("{model} ({})", obj.location),
+            );
+        }
+        let txd = format!("{}.txd", obj.txd_name);
+        if seen_txds.insert(txd.to_ascii_lowercase()) {
+            txds.push((txd, obj.location.clone()));
+        }
+    }
+
+    // TXDs nothing references are still loaded by name, e.g. by scripts, so check those too
+    for name in archives.entry_names() {
+        if name.to_ascii_lowercase().ends_with(".txd")
+            && seen_txds.insert(name.to_ascii_lowercase())
+        {
+            txds.push((name, "IMG archive".to_owned()));
+        }
+    }
+
+    // Decode with the same code the game uses, without any GPU texture formats
+    let loader = TxdLoader::default();
+    let num_txds = txds.len();
+    for (txd, location) in txds {
+        let Some(bytes) = read_asset(&install, &archives, &txd) else {
+            report.add(Category::MissingTxd, format!("{txd} ({location})"));
+            continue;
+        };
+        match loader.decode_txd(&bytes) {
+            Ok(rasters) => {
+                for (_, decoded) in rasters {
+                    if let Err(e) = decoded {
+                        report.add(Category::BadTexture, format!("{txd}: {e}"));
+                    }
+                }
+            }
+            Err(e) => report.add(Category::CorruptFile, format!("{txd}: {e}")),
+        }
+    }
+
+    println!(
+        "Checked {} objects, {} instances and {num_txds} texture dictionaries",
+        defs.objs.len(),
+        defs.instances.len()
+    );
+    if report.0.is_empty() {
+        println!("No problems found");
+        return Ok(true);
+    }
+    report.print();
+    Ok(false)
+}
+
+// Non-empty lines of the dat file with their line number, split into words with comments removed
+fn lines(text: &str) -> impl Iterator<Item = (usize, Vec<String>)> + '_ {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.split('#').next().unwrap_or_default().replace(',', " ");
+        let words: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+        (!words.is_empty()).then_some((i + 1, words))
+    })
+}
+
+// Collects the objects and instances from an IDE or IPL file without spawning anything
+fn check_def(
+    install: &GameInstall,
+    kind: DefKind,
+    file: &str,
+    defs: &mut Defs,
+    report: &mut Report,
+) {
+    let Some(path) = install.get_path(&to_path(file)) else {
+        report.add(Category::MissingFile, file.to_owned());
+        return;
+    };
+    let text = match fs::read(&path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            report.add(Category::CorruptFile, format!("{file}: {e}"));
+            return;
+        }
+    };
+
+    let def = parse_def(kind, install.inst_layout(), file, &text);
+    for e in def.errors {
+        report.add(Category::CorruptFile, e.to_string());
+    }
+    for (line, record) in def.records {
+        let location = format!("{file}:{line}");
+        match record {
+            DefRecord::Obj(obj) => {
+                defs.objs.insert(
+                    obj.id,
+                    ObjDef {
+                        model_name: obj.model_name,
+                        txd_name: obj.txd_name,
+                        location,
+                    },
+                );
+            }
+            DefRecord::Vehicle(vehicle) => {
+                defs.objs.insert(
+                    vehicle.id,
+                    ObjDef {
+                        model_name: vehicle.model_name,
+                        txd_name: vehicle.txd_name,
+                        location,
+                    },
+                );
+            }
+            DefRecord::Ped(ped) => {
+                defs.objs.insert(
+                    ped.id,
+                    ObjDef {
+                        model_name: ped.model_name,
+                        txd_name: ped.txd_name,
+                        location,
+                    },
+                );
+            }
+            DefRecord::Inst(inst) => defs.instances.push((inst.id, inst.model_name, location)),
+            DefRecord::TxdParent { .. }
+            | DefRecord::Effect(_)
+            | DefRecord::Path(_)
+            | DefRecord::Zone(_)
+            | DefRecord::Cull(_)
+            | DefRecord::Occluder(_) => {}
+        }
+    }
+}
+
+fn check_col(install: &GameInstall, file: &str, location: &str, report: &mut Report) {
+    let Some(path) = install.get_path(&to_path(file)) else {
+        report.add(Category::MissingFile, format!("{file} ({location})"));
+        return;
+    };
+    match fs::read(&path) {
+        Ok(bytes) => match many0(CollV1::parse)(&bytes) {
+            Ok((rest, _)) if rest.iter().all(|&b| b == 0) => {}
+            Ok((rest, _)) => report.add(
+                Category::CorruptFile,
+                format!("{file}: {} bytes could not be parsed", rest.len()),
+            ),
+            Err(e) => report.add(Category::CorruptFile, format!("{file}: {e}")),
+        },
+        Err(e) => report.add(Category::CorruptFile, format!("{file}: {e}")),
+    }
+}
+
+// Loose directories a model or texture dictionary is looked up in after the IMG archives,
+// these match the fallbacks of the asset reader
+fn loose_dirs(name: &str) -> &'static [&'static str] {
+    if name.to_ascii_lowercase().ends_with(".txd") {
+        &["txd", "models"]
+    } else {
+        &["models"]
+    }
+}
+
+fn asset_exists(install: &GameInstall, archives: &ImgArchives, name: &str) -> bool {
+    get_mod_path(Path::new(name)).is_some()
+        || archives.contains(name)
+        || loose_dirs(name)
+            .iter()
+            .any(|dir| install.get_path(&Path::new(dir).join(name)).is_some())
+}
+
+fn read_asset(install: &GameInstall, archives: &ImgArchives, name: &str) -> Option<Vec<u8>> {
+    if let Some(path) = get_mod_path(Path::new(name)) {
+        return fs::read(path).ok();
+    }
+    archives.get_file(name).or_else(|| {
+        loose_dirs(name)
+            .iter()
+            .find_map(|dir| install.get_path(&Path::new(dir).join(name)))
+            .and_then(|path| fs::read(path).ok())
+    })
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use bevy::{image::ImageSamplerDescriptor, prelude::*, transform::TransformSystems};
+
+use crate::{
+    clock::GameClock,
+    def::{Effect2d, Effect2dKind},
+    material::GTAMaterial,
+    to_xzy,
+};
+
+/// Corona of a light from a 2dfx entry, a sprite that always faces the camera
+#[derive(Component)]
+pub struct Corona {
+    /// Distance from the camera the corona is visible from
+    pub distance: f32,
+    /// How the light blinks, 0 is constant
+    pub flash: u8,
+}
+
+impl Corona {
+    // Whether the light is on at a time in milliseconds, odd values below 12 are the night-only
+    // variant of the value before them. seed keeps lights of the same kind from blinking in sync.
+    pub fn is_lit(&self, time_ms: u32, seed: u32, night: bool) -> bool {
+        let time = time_ms.wrapping_add(seed);
+        let flicker = (time_ms ^ seed) & 0x60 != 0;
+        // Only flickers now and then, for a few seconds at a time
+        let random_flicker = ((time_ms >> 11) ^ seed) & 3 != 0 || flicker;
+        match self.flash {
+            0 => true,
+            1 => night,
+            2 => flicker,
+            3 => night && flicker,
+            4 => time & 0x200 != 0,
+            5 => night && time & 0x200 != 0,
+            6 => time & 0x400 != 0,
+            7 => night && time & 0x400 != 0,
+            8 => time & 0x800 != 0,
+            9 => night && time & 0x800 != 0,
+            10 => random_flicker,
+            11 => night && random_flicker,
+            // The special, bridge and train crossing lights are switched by game events that
+            // aren't simulated, keep them on
+            _ => true,
+        }
+    }
+}
+
+/// Particle emitter from a 2dfx entry, facing the direction particles are emitted in
+#[derive(Component)]
+pub struct ParticleEmitter {
+    pub particle_type: u32,
+    pub scale: f32,
+}
+
+/// Mesh and materials shared by all coronas
+#[derive(Resource)]
+pub struct EffectAssets {
+    quad: Handle<Mesh>,
+    /// Corona material for every texture and color
+    materials: HashMap<(String, [u8; 4]), Handle<GTAMaterial>>,
+}
+
+impl FromWorld for EffectAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            quad: world
+                .resource_mut::<Assets<Mesh>>()
+                .add(Rectangle::new(1.0, 1.0)),
+            materials: HashMap::new(),
+        }
+    }
+}
+
+impl EffectAssets {
+    fn corona_material(
+        &mut self,
+        texture: &str,
+        color: [u8; 4],
+        materials: &mut Assets<GTAMaterial>,
+        server: &AssetServer,
+    ) -> Handle<GTAMaterial> {
+        let texture = texture.to_ascii_lowercase();
+        self.materials
+            .entry((texture.clone(), color))
+            .or_insert_with(|| {
+                let [r, g, b, _] = color;
+                materials.add(GTAMaterial {
+                    color: Color::srgb_u8(r, g, b).into(),
+                    texture: Some(server.load(format!("particle.txd#{texture}"))),
+                    sampler: ImageSamplerDescriptor::default(),
+                    ambient_fac: 0.0,
+                    diffuse_fac: 1.0,
+                    ambient_light: default(),
+                    alpha_mode: AlphaMode::Add,
+                    depth_write: false,
+                })
+            })
+            .clone()
+    }
+}
+
+// Spawns lights, coronas and particle emitters for the 2dfx entries of a model as children of
+// one of its instances. Each light is a child of its corona, so it is hidden along with it.
+pub fn spawn_effects(
+    effects: &[Effect2d],
+    parent: &mut EntityCommands,
+    assets: &mut EffectAssets,
+    materials: &mut Assets<GTAMaterial>,
+    server: &AssetServer,
+) {
+    parent.with_children(|parent| {
+        for effect in effects {
+            let translation = Vec3::from(to_xzy(effect.pos));
+            let [r, g, b, _] = effect.color;
+            match &effect.kind {
+                Effect2dKind::Light(light) => {
+                    parent
+                        .spawn((
+                            Transform::from_translation(translation)
+                                .with_scale(Vec3::splat(light.corona_size)),
+                            Mesh3d(assets.quad.clone()),
+                            MeshMaterial3d(assets.corona_material(
+                                &light.corona_texture,
+                                effect.color,
+                                materials,
+                                server,
+                            )),
+                            Corona {
+                                distance: light.distance,
+                                flash: light.flash,
+                            },
+                        ))
+                        .with_children(|corona| {
+                            corona.spawn(PointLight {
+                                color: Color::srgb_u8(r, g, b),
+                                range: light.outer_range,
+                                shadows_enabled: false,
+                                ..default()
+                            });
+                        });
+                }
+                Effect2dKind::Particle {
+                    particle_type,
+                    direction,
+                    scale,
+                } => {
+                    let direction = Vec3::from(to_xzy(*direction));
+                    let mut transform = Transform::from_translation(translation);
+                    if direction != Vec3::ZERO {
+                        transform.look_to(direction, Vec3::Y);
+                    }
+                    parent.spawn((
+                        transform,
+                        ParticleEmitter {
+                            particle_type: *particle_type,
+                            scale: *scale,
+                        },
+                    ));
+                }
+            }
+        }
+    });
+}
+
+// Turns coronas towards the camera and hides the ones that are too far away or blinked off,
+// along with their lights
+fn update_coronas(
+    time: Res<Time>,
+    clock: Res<GameClock>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+    parents: Query<&GlobalTransform, Without<Corona>>,
+    mut coronas: Query<(Entity, &mut Transform, &mut Visibility, &ChildOf, &Corona)>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    let camera_rotation = camera.compute_transform().rotation;
+    let time_ms = time.elapsed().as_millis() as u32;
+    let night = !(7..20).contains(&clock.hour);
+    for (entity, mut transform, mut visibility, child_of, corona) in &mut coronas {
+        let Ok(parent) = parents.get(child_of.parent()) else {
+            continue;
+        };
+        let position = parent.transform_point(transform.translation);
+        let visible = position.distance(camera.translation()) <= corona.distance
+            && corona.is_lit(time_ms, entity.to_bits() as u32, night);
+        visibility.set_if_neq(if visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        transform.rotation = parent.compute_transform().rotation.inverse() * camera_rotation;
+    }
+}
+
+pub struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EffectAssets>().add_systems(
+            PostUpdate,
+            update_coronas.before(TransformSystems::Propagate),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corona(flash: u8) -> Corona {
+        Corona {
+            distance: 100.0,
+            flash,
+        }
+    }
+
+    // Whether the light is on at any and at every time of the first minute
+    fn lit_at_times(flash: u8, night: bool) -> (bool, bool) {
+        let corona = corona(flash);
+        let mut lit = (0..60_000).step_by(10).map(|t| corona.is_lit(t, 0, night));
+        let first = lit.next().unwrap();
+        lit.fold((first, first), |(any, all), l| (any || l, all && l))
+    }
+
+    #[test]
+    fn constant_lights() {
+        assert_eq!(lit_at_times(0, false), (true, true));
+        assert_eq!(lit_at_times(0, true), (true, true));
+        assert_eq!(lit_at_times(1, false), (false, false));
+        assert_eq!(lit_at_times(1, true), (true, true));
+    }
+
+    #[test]
+    fn blinking_lights_are_night_only_for_odd_values() {
+        for flash in [2, 4, 6, 8, 10] {
+            assert_eq!(lit_at_times(flash, false), (true, false), "{flash}");
+            assert_eq!(
+                lit_at_times(flash + 1, false),
+                (false, false),
+                "{}",
+                flash + 1
+            );
+            assert_eq!(
+                lit_at_times(flash + 1, true),
+                (true, false),
+                "{}",
+                flash + 1
+            );
+        }
+    }
+
+    #[test]
+    fn blink_speeds() {
+        assert!(!corona(4).is_lit(0x1ff, 0, false));
+        assert!(corona(4).is_lit(0x200, 0, false));
+        assert!(!corona(6).is_lit(0x3ff, 0, false));
+        assert!(corona(6).is_lit(0x400, 0, false));
+        assert!(!corona(8).is_lit(0x7ff, 0, false));
+        assert!(corona(8).is_lit(0x800, 0, false));
+    }
+
+    #[test]
+    fn event_lights_stay_on_during_the_day() {
+        for flash in 12..=15 {
+            assert_eq!(lit_at_times(flash, false), (true, true), "{flash}");
+        }
+    }
+}
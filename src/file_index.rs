@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::PoisonError,
+    time::Duration,
+};
+
+use async_channel::Sender;
+use bevy::{
+    asset::io::{file::FileWatcher, AssetSourceEvent, AssetWatcher},
+    prelude::*,
+};
+
+use crate::FILE_INDEX;
+
+/// Index of all files and directories below a root directory, keyed by their lowercase path.
+/// GTA assumes a case-insensitive file system, so this is used to resolve paths on other platforms.
+#[derive(Default)]
+pub struct FileIndex {
+    /// Lowercase relative path -> all relative paths matching it
+    entries: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl FileIndex {
+    pub fn build(root: &Path) -> Self {
+        let mut index = Self::default();
+        index.walk(root, Path::new(""), &mut HashSet::new());
+        for matches in index.entries.values_mut() {
+            matches.sort();
+        }
+        for matches in index.ambiguous() {
+            warn!(
+                "Ambiguous path in {}: {:?} only differ in case, using {}",
+                root.display(),
+                matches,
+                matches[0].display()
+            );
+        }
+        index
+    }
+
+    // Symlinks are followed, but every directory is only entered once so link loops end
+    fn walk(&mut self, root: &Path, dir: &Path, visited: &mut HashSet<PathBuf>) {
+        let full_path = root.join(dir);
+        if !fs::canonicalize(&full_path).is_ok_and(|path| visited.insert(path)) {
+            return;
+        }
+        let Ok(iter) = fs::read_dir(full_path) else {
+            return;
+        };
+        for file in iter {
+            let Ok(file) = file else { continue };
+            let path = dir.join(file.file_name());
+            self.entries
+                .entry(lowercase(&path))
+                .or_default()
+                .push(path.clone());
+            if root.join(&path).is_dir() {
+                self.walk(root, &path, visited);
+            }
+        }
+    }
+
+    // Returns the relative path with the correct case, or None if it doesn't exist
+    pub fn get(&self, path: &Path) -> Option<&Path> {
+        let key = lowercase(path);
+        if key.as_os_str().is_empty() {
+            return Some(Path::new(""));
+        }
+        self.entries.get(&key).map(|m| m[0].as_path())
+    }
+
+    // All groups of paths which only differ in case
+    pub fn ambiguous(&self) -> impl Iterator<Item = &[PathBuf]> {
+        self.entries
+            .values()
+            .filter(|m| m.len() > 1)
+            .map(|m| m.as_slice())
+    }
+}
+
+fn lowercase(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c.to_string_lossy().to_ascii_lowercase()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// File indices of all directories we look up files in, built on first use
+#[derive(Default)]
+pub struct FileIndices(HashMap<PathBuf, FileIndex>);
+
+impl FileIndices {
+    pub fn get(&self, root: &Path) -> Option<&FileIndex> {
+        self.0.get(root)
+    }
+
+    pub fn build(&mut self, root: &Path) -> &FileIndex {
+        self.0
+            .entry(root.to_path_buf())
+            .or_insert_with(|| FileIndex::build(root))
+    }
+
+    // Called by the file watcher when anything below root changed
+    pub fn invalidate(&mut self, root: &Path) {
+        self.0.remove(root);
+    }
+}
+
+/// Watches the game and mod directories, invalidating their file index whenever something changes
+/// before passing the event on to the asset server
+pub struct IndexWatcher(Vec<FileWatcher>);
+
+impl AssetWatcher for IndexWatcher {}
+
+impl IndexWatcher {
+    pub fn new(roots: &[PathBuf], sender: Sender<AssetSourceEvent>) -> Option<Self> {
+        let mut watchers = Vec::with_capacity(roots.len());
+        for root in roots {
+            let (tx, rx) = async_channel::unbounded();
+            let watcher = match FileWatcher::new(root.clone(), tx, Duration::from_millis(300)) {
+                Ok(w) => w,
+                Err(e) => {
+                    error!("Error watching {}: {e}", root.display());
+                    return None;
+                }
+            };
+            let root = root.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                while let Ok(event) = rx.recv_blocking() {
+                    FILE_INDEX
+                        .write()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .invalidate(&root);
+                    if sender.send_blocking(event).is_err() {
+                        break;
+                    }
+                }
+            });
+            watchers.push(watcher);
+        }
+        Some(Self(watchers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_walked_once() {
+        let root = std::env::temp_dir().join(format!("gtc-file-index-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("Models")).unwrap();
+        fs::write(root.join("Models/GTA3.IMG"), b"").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("Models/loop")).unwrap();
+
+        let index = FileIndex::build(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            index.get(Path::new("models/gta3.img")),
+            Some(Path::new("Models/GTA3.IMG"))
+        );
+        assert_eq!(
+            index.get(Path::new("models/loop")),
+            Some(Path::new("Models/loop"))
+        );
+        assert_eq!(index.get(Path::new("models/loop/models")), None);
+    }
+}
@@ -4,6 +4,8 @@ use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
 use bevy::window::{CursorGrabMode, CursorOptions, PrimaryWindow};
 
+use crate::zones::ZoneTracker;
+
 /// Keeps track of mouse motion events, pitch, and yaw
 #[derive(Resource, Default)]
 struct InputState {
@@ -81,6 +83,7 @@ fn setup_game_camera(mut commands: Commands) {
         Transform::from_xyz(0.0, 300.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
         Camera3d { ..default() },
         FlyCam,
+        ZoneTracker::default(),
         RigidBody::Dynamic,
         Collider::sphere(1.0),
         GravityScale(0.),
@@ -0,0 +1,330 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::archive::{read_dir_entries, write_dir_entries, DirEntry, SECTOR_SIZE};
+
+/// Names are stored in 24 bytes including the terminating NUL
+const MAX_NAME_LEN: usize = 23;
+
+/// Where the data of an entry comes from when the archive gets rebuilt
+enum EntryData {
+    /// Sectors in the archive we opened
+    Archive { offset: u32, size: u32 },
+    /// New data that was added or replaced an entry
+    New(Vec<u8>),
+}
+
+struct EditEntry {
+    name: String,
+    data: EntryData,
+}
+
+/// Editable copy of the directory of an IMG archive. Changes are kept in memory until `write`
+/// rebuilds the .img/.dir pair, which also drops any unused space between entries.
+pub struct ImgEditor {
+    path: PathBuf,
+    entries: Vec<EditEntry>,
+}
+
+impl ImgEditor {
+    // Opens an existing archive, or starts an empty one if neither the .img nor the .dir exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let dir_path = path.with_extension("dir");
+        let entries = if !path.exists() && !dir_path.exists() {
+            Vec::new()
+        } else {
+            read_dir_entries(&dir_path)?
+                .into_iter()
+                .map(|e| EditEntry {
+                    name: e.name,
+                    data: EntryData::Archive {
+                        offset: e.offset,
+                        size: e.size,
+                    },
+                })
+                .collect()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    // Adds a file to the archive, replacing an entry with the same name in place.
+    // Returns whether an entry was replaced.
+    pub fn add(&mut self, name: &str, data: Vec<u8>) -> Result<bool> {
+        if !name.is_ascii() || name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(format!(
+                "invalid entry name {name:?}, names must be ASCII and at most {MAX_NAME_LEN} characters"
+            )
+            .into());
+        }
+        match self.find(name) {
+            Some(i) => {
+                self.entries[i].data = EntryData::New(data);
+                Ok(true)
+            }
+            None => {
+                self.entries.push(EditEntry {
+                    name: name.to_owned(),
+                    data: EntryData::New(data),
+                });
+                Ok(false)
+            }
+        }
+    }
+
+    // Removes an entry, returns false if there is none with this name
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.find(name) {
+            Some(i) => {
+                self.entries.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    // Rebuilds the archive with all entries packed back to back, each starting on a sector
+    // boundary. Both files are written next to the originals first and then moved over them,
+    // see replace_files for what happens when that fails. Returns the size of the new .img.
+    pub fn write(self) -> Result<u64> {
+        let img_tmp = self.path.with_extension("img.tmp");
+        let dir_tmp = self.path.with_extension("dir.tmp");
+        let written =
+            write_temp_files(&self.path, self.entries, &img_tmp, &dir_tmp).and_then(|size| {
+                replace_files(&self.path, &img_tmp, &dir_tmp)?;
+                Ok(size)
+            });
+        if written.is_err() {
+            // Don't leave half-written files next to the archive
+            let _ = fs::remove_file(&img_tmp);
+            let _ = fs::remove_file(&dir_tmp);
+        }
+        written
+    }
+}
+
+// Writes the entries to img_tmp and their directory to dir_tmp, reading kept entries from the
+// archive at path. Returns the size of the new .img.
+fn write_temp_files(
+    path: &Path,
+    entries: Vec<EditEntry>,
+    img_tmp: &Path,
+    dir_tmp: &Path,
+) -> Result<u64> {
+    let mut source = match File::open(path) {
+        Ok(file) => Some((file.metadata()?.len(), file)),
+        Err(_) => None,
+    };
+    let mut out = File::create(img_tmp)?;
+    let mut dir = Vec::with_capacity(entries.len());
+    let mut sector = 0u32;
+    for entry in entries {
+        let mut data = match entry.data {
+            EntryData::New(data) => data,
+            EntryData::Archive { offset, size } => {
+                let Some((len, source)) = source.as_mut() else {
+                    return Err(format!("{} not found", path.display()).into());
+                };
+                // The last entry may not be padded to a full sector, like in ImgArchive::get_file
+                let start = offset as u64 * SECTOR_SIZE;
+                let end = (start + size as u64 * SECTOR_SIZE).min(*len);
+                let Some(available) = end.checked_sub(start) else {
+                    return Err(format!("{} lies outside of {}", entry.name, path.display()).into());
+                };
+                let mut data = vec![0; available as usize];
+                source.seek(SeekFrom::Start(start))?;
+                source.read_exact(&mut data).map_err(|e| {
+                    format!("error reading {} from {}: {e}", entry.name, path.display())
+                })?;
+                data
+            }
+        };
+        let size = (data.len() as u64).div_ceil(SECTOR_SIZE);
+        data.resize((size * SECTOR_SIZE) as usize, 0);
+        out.write_all(&data)?;
+
+        dir.push(DirEntry {
+            offset: sector,
+            size: size as u32,
+            name: entry.name,
+        });
+        sector += size as u32;
+    }
+    out.sync_all()?;
+    write_dir_entries(dir_tmp, &dir)?;
+    Ok(sector as u64 * SECTOR_SIZE)
+}
+
+// Moves the rebuilt .img and .dir over the originals. The two renames can't happen at once, so
+// the old .img is kept as a backup until the new .dir is in place and moved back if either
+// rename fails. The old .dir is only replaced by the last rename, so it needs no backup.
+// If the process dies in between, the old .img is left behind as .img.bak.
+fn replace_files(img: &Path, img_tmp: &Path, dir_tmp: &Path) -> std::io::Result<()> {
+    let backup = img.with_extension("img.bak");
+    let has_original = img.exists();
+    if has_original {
+        fs::rename(img, &backup)?;
+    }
+    let replaced =
+        fs::rename(img_tmp, img).and_then(|()| fs::rename(dir_tmp, img.with_extension("dir")));
+    match replaced {
+        Ok(()) if has_original => fs::remove_file(&backup),
+        Ok(()) => Ok(()),
+        Err(e) => {
+            if has_original {
+                fs::rename(&backup, img)?;
+            } else {
+                let _ = fs::remove_file(img);
+            }
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR: usize = SECTOR_SIZE as usize;
+
+    // Path of an archive in an empty directory only used by one test
+    fn archive_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gtc-img-edit-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("test.img")
+    }
+
+    // Writes an archive from raw .img bytes and its directory
+    fn write_archive(path: &Path, img: &[u8], entries: &[(&str, u32, u32)]) {
+        fs::write(path, img).unwrap();
+        let entries: Vec<DirEntry> = entries
+            .iter()
+            .map(|&(name, offset, size)| DirEntry {
+                offset,
+                size,
+                name: name.to_owned(),
+            })
+            .collect();
+        write_dir_entries(&path.with_extension("dir"), &entries).unwrap();
+    }
+
+    // The directory as (name, offset, size) and the contents of the .img
+    fn read_archive(path: &Path) -> (Vec<(String, u32, u32)>, Vec<u8>) {
+        let entries = read_dir_entries(&path.with_extension("dir"))
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.name, e.offset, e.size))
+            .collect();
+        (entries, fs::read(path).unwrap())
+    }
+
+    fn entries(list: &[(&str, u32, u32)]) -> Vec<(String, u32, u32)> {
+        list.iter()
+            .map(|&(name, offset, size)| (name.to_owned(), offset, size))
+            .collect()
+    }
+
+    // data padded with zeros to whole sectors
+    fn padded(data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data.resize(data.len().div_ceil(SECTOR) * SECTOR, 0);
+        data
+    }
+
+    fn assert_no_temp_files(path: &Path) {
+        for extension in ["img.tmp", "dir.tmp", "img.bak"] {
+            assert!(!path.with_extension(extension).exists(), "{extension} left");
+        }
+    }
+
+    #[test]
+    fn add_to_new_archive() {
+        let path = archive_path("add");
+        let mut editor = ImgEditor::open(&path).unwrap();
+        assert!(!editor.add("a.txd", vec![1; 10]).unwrap());
+        assert!(!editor.add("b.dff", vec![2; SECTOR + 1]).unwrap());
+        assert_eq!(editor.write().unwrap(), 3 * SECTOR_SIZE);
+
+        let (dir, img) = read_archive(&path);
+        assert_eq!(dir, entries(&[("a.txd", 0, 1), ("b.dff", 1, 2)]));
+        assert_eq!(img, [padded(&[1; 10]), padded(&[2; SECTOR + 1])].concat());
+        assert_no_temp_files(&path);
+    }
+
+    #[test]
+    fn replace_keeps_the_position_of_the_entry() {
+        let path = archive_path("replace");
+        let img = [padded(&[1; 5]), padded(&[2; 5]), padded(&[3; 5])].concat();
+        write_archive(&path, &img, &[("a", 0, 1), ("b", 1, 1), ("c", 2, 1)]);
+
+        let mut editor = ImgEditor::open(&path).unwrap();
+        assert!(editor.add("B", vec![4; 2 * SECTOR]).unwrap());
+        assert_eq!(editor.write().unwrap(), 4 * SECTOR_SIZE);
+
+        let (dir, img) = read_archive(&path);
+        assert_eq!(dir, entries(&[("a", 0, 1), ("b", 1, 2), ("c", 3, 1)]));
+        assert_eq!(
+            img,
+            [padded(&[1; 5]), vec![4; 2 * SECTOR], padded(&[3; 5])].concat()
+        );
+    }
+
+    #[test]
+    fn remove_packs_the_remaining_entries() {
+        let path = archive_path("remove");
+        let img = [padded(&[1; 5]), padded(&[2; 5]), padded(&[3; 5])].concat();
+        write_archive(&path, &img, &[("a", 0, 1), ("b", 1, 1), ("c", 2, 1)]);
+
+        let mut editor = ImgEditor::open(&path).unwrap();
+        assert!(editor.remove("A"));
+        assert!(!editor.remove("missing"));
+        assert_eq!(editor.write().unwrap(), 2 * SECTOR_SIZE);
+
+        let (dir, img) = read_archive(&path);
+        assert_eq!(dir, entries(&[("b", 0, 1), ("c", 1, 1)]));
+        assert_eq!(img, [padded(&[2; 5]), padded(&[3; 5])].concat());
+    }
+
+    #[test]
+    fn rebuild_drops_gaps_and_pads_an_unpadded_last_entry() {
+        let path = archive_path("rebuild");
+        // Two unused sectors between the entries, and the last entry stops short of its sector
+        let img = [padded(&[1; 5]), vec![9; 2 * SECTOR], vec![2; 100]].concat();
+        write_archive(&path, &img, &[("a", 0, 1), ("b", 3, 1)]);
+
+        let editor = ImgEditor::open(&path).unwrap();
+        assert_eq!(editor.write().unwrap(), 2 * SECTOR_SIZE);
+
+        let (dir, img) = read_archive(&path);
+        assert_eq!(dir, entries(&[("a", 0, 1), ("b", 1, 1)]));
+        assert_eq!(img, [padded(&[1; 5]), padded(&[2; 100])].concat());
+        assert_no_temp_files(&path);
+    }
+
+    #[test]
+    fn failed_rebuild_leaves_the_archive_untouched() {
+        let path = archive_path("failed");
+        let img = padded(&[1; 5]);
+        write_archive(&path, &img, &[("a", 0, 1), ("b", 5, 1)]);
+        let before = read_archive(&path);
+
+        let editor = ImgEditor::open(&path).unwrap();
+        assert!(editor.write().is_err());
+        assert_eq!(read_archive(&path), before);
+        assert_no_temp_files(&path);
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use crate::{
+    def::InstLayout,
+    utils::{get_mod_path, get_path_in},
+};
+
+/// The game and release found in a game directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameVersion {
+    Gta3Pc,
+    /// The PC release sold on Steam, which ships a different executable
+    Gta3Steam,
+    ViceCity,
+}
+
+impl GameVersion {
+    pub fn name(self) -> &'static str {
+        match self {
+            GameVersion::Gta3Pc => "GTA III (PC)",
+            GameVersion::Gta3Steam => "GTA III (Steam)",
+            GameVersion::ViceCity => "GTA Vice City",
+        }
+    }
+
+    pub fn is_gta3(self) -> bool {
+        matches!(self, GameVersion::Gta3Pc | GameVersion::Gta3Steam)
+    }
+}
+
+/// A game directory and the release it holds, all game files are looked up through this
+#[derive(Resource, Clone, Debug)]
+pub struct GameInstall {
+    pub root: PathBuf,
+    pub version: GameVersion,
+}
+
+impl GameInstall {
+    // Figures out which game is installed in root from the files present
+    pub fn detect(root: &Path) -> Result<Self> {
+        let has = |path: &str| get_path_in(root, Path::new(path)).is_some();
+        let version = if has("data/gta.dat") {
+            // San Andreas packs its archives as VER2 IMG files without a .dir
+            return Err(format!(
+                "{} holds GTA San Andreas, whose IMG archives are not supported",
+                root.display()
+            )
+            .into());
+        } else if has("data/gta_vc.dat") {
+            GameVersion::ViceCity
+        } else if has("data/gta3.dat") {
+            // Files extracted from the Android/iOS port keep their textures in texdb instead of
+            // TXDs, which nothing here can read
+            if has("texdb") {
+                return Err(format!(
+                    "{} holds the mobile release of GTA III, which is not supported",
+                    root.display()
+                )
+                .into());
+            }
+            // Steam installs ship the Steam client library and app id next to the executable
+            if has("steam_api.dll") || has("steam_appid.txt") {
+                GameVersion::Gta3Steam
+            } else {
+                GameVersion::Gta3Pc
+            }
+        } else {
+            return Err(format!("no GTA installation found in {}", root.display()).into());
+        };
+        Ok(Self {
+            root: root.to_path_buf(),
+            version,
+        })
+    }
+
+    // Case-insensitive path search from the game directory, files in mod directories take priority
+    pub fn get_path(&self, path: &Path) -> Option<PathBuf> {
+        get_mod_path(path).or_else(|| get_path_in(&self.root, path))
+    }
+
+    // The main data file listing all other definition files
+    pub fn dat_file(&self) -> &'static str {
+        match self.version {
+            GameVersion::ViceCity => "data/gta_vc.dat",
+            _ => "data/gta3.dat",
+        }
+    }
+
+    // The archive that is always loaded, other archives are listed in the dat file
+    pub fn img_file(&self) -> &'static str {
+        "models/gta3.img"
+    }
+
+    pub fn water_file(&self) -> &'static str {
+        "data/waterpro.dat"
+    }
+
+    pub fn inst_layout(&self) -> InstLayout {
+        match self.version {
+            GameVersion::ViceCity => InstLayout::ViceCity,
+            _ => InstLayout::Gta3,
+        }
+    }
+
+    pub fn script_file(&self) -> &'static str {
+        "data/main.scm"
+    }
+}
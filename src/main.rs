@@ -1,20 +1,41 @@
+mod archive;
 mod assets;
+mod cli;
+mod clock;
+mod cull;
 mod dat;
+mod def;
+mod doctor;
+mod effects;
+mod file_index;
+mod img_edit;
+mod install;
 mod material;
 mod mesh;
 mod objects;
+mod occlusion;
+mod paths;
+mod raster;
 mod scm;
 mod utils;
+mod zones;
 
 mod flycam;
 
-use std::{path::PathBuf, sync::Mutex};
+use std::{
+    path::PathBuf,
+    sync::{OnceLock, PoisonError, RwLock},
+};
 
-use assets::{GTAAssetReader, Txd, TxdLoader};
+use archive::{ImgArchives, SharedArchives};
+use assets::{
+    ColLoader, CollisionArchive, DffLoader, DffSettings, GTAAssetReader, Model, TxdPlugin,
+};
 use avian3d::prelude::*;
 use bevy::{
-    asset::io::{AssetSourceBuilder, AssetSourceId},
+    asset::io::{AssetSourceBuilder, AssetSourceId, AssetWatcher},
     audio::AudioPlugin,
+    camera::visibility::VisibilitySystems,
     image::{ImageAddressMode, ImageSamplerDescriptor},
     log::LogPlugin,
     prelude::*,
@@ -22,21 +43,28 @@ use bevy::{
 use bevy_inspector_egui::{bevy_egui::EguiPlugin, quick::WorldInspectorPlugin};
 
 use clap::Parser;
-use dat::GameData;
+use clock::ClockPlugin;
+use cull::CullPlugin;
+use dat::{GameData, MapAreas};
+use effects::EffectsPlugin;
+use file_index::{FileIndices, IndexWatcher};
 use flycam::*;
+use install::GameInstall;
 use material::{GTAMaterial, GTAMaterialPlugin};
-use mesh::load_dff;
-use objects::{spawn_obj, ObjHandles};
-use rw_rs::{bsf::*, img::Img};
+use objects::{
+    apply_hidden, instantiate_collision, instantiate_models, spawn_obj, ObjHandles, PendingModel,
+};
+use occlusion::OcclusionPlugin;
+use paths::PathsPlugin;
 
 use lazy_static::lazy_static;
 use scm::ScriptEnginePlugin;
 use utils::to_xzy;
+use zones::ZonesPlugin;
 lazy_static! {
-    static ref GTA_DIR: PathBuf = PathBuf::from(std::env::var("GTA_DIR").unwrap_or(".".into()));
-    static ref IMG: Mutex<Img<'static>> =
-        Mutex::new(Img::new(&GTA_DIR.join("models/gta3.img")).expect("gta3.img not found"));
+    static ref FILE_INDEX: RwLock<FileIndices> = RwLock::new(FileIndices::default());
 }
+static MOD_DIRS: OnceLock<Vec<PathBuf>> = OnceLock::new();
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -45,21 +73,83 @@ struct Args {
     viewer: bool,
     #[arg(long)]
     script: bool,
+    /// Game directory, defaults to the GTA_DIR environment variable or the working directory
+    #[arg(long, env = "GTA_DIR", default_value = ".")]
+    gta_dir: PathBuf,
+    /// Additional IMG archive, overrides files in gta3.img and archives from gta3.dat
+    #[arg(long)]
+    img: Vec<PathBuf>,
+    /// Directory with loose files that override the game files, later directories take priority
+    #[arg(long)]
+    mod_dir: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 fn main() -> AppExit {
     let args = Args::parse();
 
-    if !GTA_DIR.join("data/gta3.dat").exists() {
-        error!(
-            "GTA files not found, set working directory or set the GTA_DIR environment variable."
-        );
+    if let Some(dir) = args.mod_dir.iter().find(|d| !d.is_dir()) {
+        error!("Mod directory {} not found", dir.display());
         return AppExit::error();
     }
+    MOD_DIRS.set(args.mod_dir).unwrap();
+
+    if let Some(command) = args.command {
+        return cli::run(command, &args.gta_dir, &args.img);
+    }
+
+    let install = match GameInstall::detect(&args.gta_dir) {
+        Ok(install) => install,
+        Err(e) => {
+            error!("{e}, set the working directory or pass --gta-dir");
+            return AppExit::error();
+        }
+    };
+    info!(
+        "Found {} in {}",
+        install.version.name(),
+        install.root.display()
+    );
+    if !install.version.is_gta3() {
+        warn!(
+            "{} is not fully supported, expect missing or broken assets",
+            install.version.name()
+        );
+    }
+
+    let mut archives = match ImgArchives::new(&install.root.join(install.img_file())) {
+        Ok(archives) => archives,
+        Err(e) => {
+            error!("Error loading {}: {e}", install.img_file());
+            return AppExit::error();
+        }
+    };
+    for img in &args.img {
+        if let Err(e) = archives.add(img) {
+            error!("Error loading IMG archive: {e}");
+            return AppExit::error();
+        }
+    }
+    let img = SharedArchives::new(archives);
+    let reader_img = img.clone();
+    let reader_install = install.clone();
+    let watched_root = install.root.clone();
+
     let mut app = App::new();
     app.register_asset_source(
         AssetSourceId::default(),
-        AssetSourceBuilder::new(move || Box::new(GTAAssetReader)),
+        AssetSourceBuilder::new(move || {
+            Box::new(GTAAssetReader {
+                install: reader_install.clone(),
+                img: reader_img.clone(),
+            })
+        })
+        .with_watcher(move |sender| {
+            let mut roots = vec![watched_root.clone()];
+            roots.extend(MOD_DIRS.get().into_iter().flatten().cloned());
+            IndexWatcher::new(&roots, sender).map(|w| Box::new(w) as Box<dyn AssetWatcher>)
+        }),
     )
     .add_plugins(
         DefaultPlugins
@@ -84,15 +174,32 @@ fn main() -> AppExit {
             })
             .disable::<AudioPlugin>(),
     )
-    .register_asset_loader(TxdLoader)
-    .init_asset::<Txd>()
+    .add_plugins(TxdPlugin)
+    .register_asset_loader(DffLoader)
+    .init_asset::<Model>()
+    .register_asset_loader(ColLoader)
+    .init_asset::<CollisionArchive>()
     .add_plugins(GTAMaterialPlugin)
+    .add_plugins(ClockPlugin)
+    .add_plugins(EffectsPlugin)
+    .add_plugins(PathsPlugin)
+    .add_plugins(ZonesPlugin)
+    .add_plugins(CullPlugin)
+    .add_plugins(OcclusionPlugin)
     .add_plugins((
         PhysicsPlugins::default(), /*PhysicsDebugPlugin::default()*/
     ))
     .add_plugins((EguiPlugin::default(), WorldInspectorPlugin::new()))
+    .add_systems(PreStartup, build_file_indices)
     .insert_resource(GameData::default())
+    .insert_resource(img)
+    .insert_resource(install)
     .add_observer(spawn_obj)
+    .add_systems(Update, (instantiate_models, instantiate_collision))
+    .add_systems(
+        PostUpdate,
+        apply_hidden.before(VisibilitySystems::VisibilityPropagate),
+    )
     .insert_resource(ObjHandles::default());
 
     if args.viewer {
@@ -110,15 +217,27 @@ fn main() -> AppExit {
     app.run()
 }
 
+// Index the game and mod directories up front so lookups during loading don't have to
+fn build_file_indices(install: Res<GameInstall>) {
+    let mut index = FILE_INDEX.write().unwrap_or_else(PoisonError::into_inner);
+    index.build(&install.root);
+    for dir in MOD_DIRS.get().into_iter().flatten() {
+        index.build(dir);
+    }
+}
+
 fn setup_game(
     mut commands: Commands,
     mut game_data: ResMut<GameData>,
     mut materials: ResMut<Assets<GTAMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
     asset_server: Res<AssetServer>,
+    install: Res<GameInstall>,
+    img: Res<SharedArchives>,
+    mut areas: MapAreas,
 ) {
     game_data
-        .load_dat(&mut commands)
+        .load_dat(&install, &mut commands, &asset_server, &img, &mut areas)
         .expect("Error loading gta3.dat");
 
     const WATER_TILE_SIZE: f32 = 32.0;
@@ -130,7 +249,7 @@ fn setup_game(
         Visibility::Visible,
         WaterParent,
     ));
-    match game_data.load_water() {
+    match game_data.load_water(&install) {
         Ok(()) => {
             for i in 0..128 * 128 {
                 let height = game_data.water_level[i];
@@ -163,6 +282,8 @@ fn setup_game(
                             blue: 0.0,
                             alpha: 1.0,
                         },
+                        alpha_mode: AlphaMode::AlphaToCoverage,
+                        depth_write: true,
                     })),
                     Transform::from_xyz(
                         -(f32::floor((i as f32) / 128.0) * WATER_TILE_SIZE),
@@ -183,25 +304,14 @@ fn setup_viewer(
     mut meshes: ResMut<Assets<Mesh>>,
     asset_server: Res<AssetServer>,
 ) {
-    let tl = IMG.lock().unwrap().get_file("trafficlight1.dff").unwrap();
-    let (_, tl) = Chunk::parse(&tl).unwrap();
-    let meshes_vec = load_dff(&tl, "dyntraffic", &asset_server)
-        .into_iter()
-        .next_back()
-        .unwrap()
-        .into_iter()
-        .map(|(m, mat)| (meshes.add(m), materials.add(mat)))
-        .collect::<Vec<_>>();
-
-    let mut ent = commands.spawn((
+    let tl = asset_server.load_with_settings("trafficlight1.dff", |s: &mut DffSettings| {
+        s.txd_name = "dyntraffic".into()
+    });
+    commands.spawn((
         Transform::from_xyz(0.0, 290.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
         Visibility::Visible,
+        PendingModel(tl),
     ));
-    ent.with_children(|parent| {
-        for (mesh, material) in meshes_vec {
-            parent.spawn((Mesh3d(mesh), MeshMaterial3d(material)));
-        }
-    });
 
     commands.spawn((
         Mesh3d(meshes.add(Plane3d::new(Vec3::X, Vec2 { x: 32., y: 32. }))),
@@ -222,6 +332,8 @@ fn setup_viewer(
                 blue: 0.0,
                 alpha: 1.0,
             },
+            alpha_mode: AlphaMode::AlphaToCoverage,
+            depth_write: true,
         })),
     ));
 }
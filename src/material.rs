@@ -2,8 +2,12 @@ use bevy::{
     asset::embedded_asset, image::ImageSamplerDescriptor, prelude::*,
     render::render_resource::AsBindGroup, shader::ShaderRef,
 };
+use bytemuck::{Pod, Zeroable};
+
+use crate::def::ObjectFlags;
 
 #[derive(AsBindGroup, Debug, Clone, Asset, TypePath)]
+#[bind_group_data(GTAMaterialKey)]
 pub struct GTAMaterial {
     #[uniform(0)]
     pub color: LinearRgba,
@@ -20,6 +24,40 @@ pub struct GTAMaterial {
     //TODO: should be global, not instance specific
     #[uniform(5)]
     pub ambient_light: LinearRgba,
+
+    /// Cutout by default, models flagged in the IDE or with opaque textures change this
+    pub alpha_mode: AlphaMode,
+    pub depth_write: bool,
+}
+
+impl GTAMaterial {
+    // Applies the IDE flags of the object the material belongs to
+    pub fn apply_flags(&mut self, flags: ObjectFlags) {
+        if flags.contains(ObjectFlags::ADDITIVE) {
+            self.alpha_mode = AlphaMode::Add;
+        } else if flags.contains(ObjectFlags::DRAW_LAST) {
+            self.alpha_mode = AlphaMode::Blend;
+        }
+        self.depth_write = !flags.contains(ObjectFlags::NO_ZBUFFER_WRITE);
+        if flags.contains(ObjectFlags::IGNORE_LIGHTING) {
+            self.ambient_fac = 0.0;
+        }
+    }
+}
+
+/// Material settings that need a different pipeline
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Pod, Zeroable)]
+pub struct GTAMaterialKey {
+    depth_write: u32,
+}
+
+impl From<&GTAMaterial> for GTAMaterialKey {
+    fn from(material: &GTAMaterial) -> Self {
+        Self {
+            depth_write: material.depth_write as u32,
+        }
+    }
 }
 
 impl Material for GTAMaterial {
@@ -32,16 +70,22 @@ impl Material for GTAMaterial {
     }
 
     fn alpha_mode(&self) -> AlphaMode {
-        AlphaMode::AlphaToCoverage
+        self.alpha_mode
     }
 
     fn specialize(
         _pipeline: &bevy::pbr::MaterialPipeline,
         descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
         _layout: &bevy::mesh::MeshVertexBufferLayoutRef,
-        _key: bevy::pbr::MaterialPipelineKey<Self>,
+        key: bevy::pbr::MaterialPipelineKey<Self>,
     ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
+        // GTA III draws everything double sided, which also covers DISABLE_BACKFACE_CULLING
         descriptor.primitive.cull_mode = None;
+        if key.bind_group_data.depth_write == 0 {
+            if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+                depth_stencil.depth_write_enabled = false;
+            }
+        }
 
         Ok(())
     }
@@ -1,19 +1,17 @@
 use bevy::{
-    asset::RenderAssetUsages,
-    image::{ImageAddressMode, ImageSamplerDescriptor},
-    mesh::PrimitiveTopology,
-    prelude::*,
+    asset::RenderAssetUsages, image::ImageSamplerDescriptor, mesh::PrimitiveTopology, prelude::*,
 };
-use rw_rs::bsf::{tex::TextureAddressingMode, Chunk, ChunkContent};
+use rw_rs::bsf::{Chunk, ChunkContent};
 
-use crate::{material::GTAMaterial, utils::to_xzy};
+use crate::{
+    material::GTAMaterial,
+    raster::{address_mode, filter_mode},
+    utils::to_xzy,
+};
 
-pub fn load_dff(
-    bsf: &Chunk,
-    txd_name: &str,
-    server: &Res<AssetServer>,
-    //images: &ResMut<Assets<Image>>,
-) -> Vec<Vec<(Mesh, GTAMaterial)>> {
+// Returns the meshes of every geometry with their material and the name of the texture it uses,
+// the texture itself gets looked up in the TXDs once they are loaded
+pub fn load_dff(bsf: &Chunk) -> Vec<Vec<(Mesh, GTAMaterial, Option<String>)>> {
     let mut res = Vec::new();
     for geometry_chunk in &bsf
         .get_children()
@@ -141,64 +139,20 @@ pub fn load_dff(
                     mesh.insert_indices(bevy::mesh::Indices::U16(used_triangles));
 
                     // Material
-                    let mut tex_handle: Option<Handle<Image>> = None;
+                    let mut texture_name = None;
                     let mut sampler: ImageSamplerDescriptor = Default::default();
                     if let Some(tex_chunk) = mat_chunk.get_children().first() {
                         if let ChunkContent::Texture(tex) = &tex_chunk.content {
                             if let ChunkContent::String(tex_name) =
                                 &tex_chunk.get_children()[0].content
                             {
-                                let tex_name = tex_name.to_ascii_lowercase();
-                                let tex_path = format!("{txd_name}.txd#{tex_name}");
-                                debug!("Loading {}", tex_path);
-
-                                let tex_img: Handle<Image> = server.load(tex_path);
-                                tex_handle = Some(tex_img);
+                                texture_name = Some(tex_name.to_ascii_lowercase());
 
-                                sampler.address_mode_u = match tex.addressing[0] {
-                                    TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS => {
-                                        todo!()
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSWRAP => {
-                                        ImageAddressMode::Repeat
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSMIRROR => {
-                                        ImageAddressMode::MirrorRepeat
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSCLAMP => {
-                                        ImageAddressMode::ClampToEdge
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSBORDER => {
-                                        ImageAddressMode::ClampToBorder
-                                    }
-                                };
-                                sampler.address_mode_v = match tex.addressing[1] {
-                                    TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS => {
-                                        todo!()
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSWRAP => {
-                                        ImageAddressMode::Repeat
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSMIRROR => {
-                                        ImageAddressMode::MirrorRepeat
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSCLAMP => {
-                                        ImageAddressMode::ClampToEdge
-                                    }
-                                    TextureAddressingMode::TEXTUREADDRESSBORDER => {
-                                        ImageAddressMode::ClampToBorder
-                                    }
-                                };
-
-                                /*let filter = match tex.filtering {
-                                    TextureFilteringMode::FILTERNAFILTERMODE => todo!(),
-                                    TextureFilteringMode::FILTERNEAREST => ImageFilterMode::Nearest,
-                                    TextureFilteringMode::FILTERLINEAR => ImageFilterMode::Linear,
-                                    TextureFilteringMode::FILTERMIPNEAREST => todo!(),
-                                    TextureFilteringMode::FILTERMIPLINEAR => todo!(),
-                                    TextureFilteringMode::FILTERLINEARMIPNEAREST => todo!(),
-                                    TextureFilteringMode::FILTERLINEARMIPLINEAR => todo!(),
-                                };*/
+                                sampler.address_mode_u = address_mode(&tex.addressing[0]);
+                                sampler.address_mode_v = address_mode(&tex.addressing[1]);
+                                (sampler.min_filter, sampler.mipmap_filter) =
+                                    filter_mode(&tex.filtering);
+                                sampler.mag_filter = sampler.min_filter;
                             }
                         }
                     }
@@ -208,14 +162,16 @@ pub fn load_dff(
 
                     let mat = GTAMaterial {
                         color: LinearRgba::from_f32_array(mat.color.as_rgba_arr()),
-                        texture: tex_handle,
+                        texture: None,
                         sampler,
                         ambient_fac: surf_prop.ambient,
                         diffuse_fac: surf_prop.diffuse,
                         ambient_light: default(),
+                        alpha_mode: AlphaMode::AlphaToCoverage,
+                        depth_write: true,
                     };
 
-                    mesh_mat_vec.push((mesh, mat))
+                    mesh_mat_vec.push((mesh, mat, texture_name))
                 }
             }
         }
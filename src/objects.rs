@@ -2,9 +2,17 @@ use std::collections::HashMap;
 
 use avian3d::prelude::*;
 use bevy::prelude::*;
-use rw_rs::{bsf::Chunk, col::CollV1};
+use bitflags::bitflags;
+use rw_rs::col::CollV1;
 
-use crate::{dat::GameData, material::GTAMaterial, mesh::load_dff, IMG};
+use crate::{
+    assets::{CollisionArchive, DffSettings, Model, Txd},
+    clock::TimedObject,
+    dat::GameData,
+    effects::{spawn_effects, EffectAssets},
+    material::GTAMaterial,
+    paths::PathGraph,
+};
 
 #[derive(Event)]
 pub struct SpawnObject {
@@ -19,26 +27,24 @@ pub struct SpawnObject {
 pub fn spawn_obj(
     trigger: On<SpawnObject>,
     game_data: Res<GameData>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<GTAMaterial>>,
     server: Res<AssetServer>,
+    mut effect_assets: ResMut<EffectAssets>,
+    mut materials: ResMut<Assets<GTAMaterial>>,
+    mut paths: ResMut<PathGraph>,
     mut commands: Commands,
 ) {
     let data = trigger.event();
     debug!("loading {}", data.name);
 
-    let ide;
-    if data.id != 0 {
-        ide = game_data
-            .ide
-            .get_by_id(data.id)
-            .expect("ide id should exist");
+    let ide = if data.id != 0 {
+        game_data.ide.get_by_id(data.id)
     } else if !data.name.is_empty() {
-        ide = game_data
-            .ide
-            .get_by_model_name(&data.name)
-            .expect("ide name should exist");
+        game_data.ide.get_by_model_name(&data.name)
     } else {
+        None
+    };
+    // The IDE line of the object may have been skipped because it failed to parse
+    let Some(ide) = ide else {
         error!(
             "tried to spawn IPL with invalid IDE id {} and/or name {}",
             data.id, data.name
@@ -55,24 +61,15 @@ pub fn spawn_obj(
         return;
     }
 
-    let file = IMG
-        .lock()
-        .unwrap()
-        .get_file(&format!("{}.dff", data.name))
-        .unwrap_or_else(|| panic!("{} not found in img", data.name));
-    let (_, bsf) = Chunk::parse(&file).unwrap();
-    let meshes_vec = load_dff(&bsf, &ide.txd_name, &server)
-        .into_iter()
-        .next_back()
-        .unwrap_or_default()
-        .into_iter()
-        .map(|(m, mat)| (meshes.add(m), materials.add(mat)))
-        .collect::<Vec<_>>();
-
-    if meshes_vec.is_empty() {
-        warn!("{} contained zero meshes", data.name);
-        return;
-    }
+    let txd_name = ide.txd_name.clone();
+    let txd_parents = game_data.ide.get_txd_parents(&txd_name);
+    let flags = ide.flags;
+    let model =
+        server.load_with_settings(format!("{}.dff", data.name), move |s: &mut DffSettings| {
+            s.txd_name = txd_name.clone();
+            s.txd_parents = txd_parents.clone();
+            s.flags = flags;
+        });
 
     let mut ent = {
         if let Some(e) = trigger.handle {
@@ -81,62 +78,230 @@ pub fn spawn_obj(
             commands.spawn_empty()
         }
     };
+    let transform = Transform {
+        translation: data.pos.into(),
+        scale: data.scale.into(),
+        rotation: data.rot,
+    };
+    // Objects created by scripts can move, only map objects carry paths
+    if trigger.handle.is_none() {
+        for group in game_data.ide.get_paths(ide.id) {
+            paths.add_group(group, &transform);
+        }
+    }
     ent.insert((
-        Transform {
-            translation: data.pos.into(),
-            scale: data.scale.into(),
-            rotation: data.rot,
-        },
+        transform,
         Visibility::Visible,
+        HiddenBy::empty(),
+        PendingModel(model),
     ));
 
-    ent.with_children(|parent| {
-        for (mesh, material) in meshes_vec {
-            parent.spawn((Mesh3d(mesh), MeshMaterial3d(material)));
+    if let Some(time) = ide.time {
+        ent.insert(TimedObject(time));
+    }
+
+    let effects = game_data.ide.get_effects(ide.id);
+    if !effects.is_empty() {
+        spawn_effects(
+            effects,
+            &mut ent,
+            &mut effect_assets,
+            &mut materials,
+            &server,
+        );
+    }
+
+    if !game_data.col.is_empty() {
+        ent.insert(PendingCollision(data.name.to_ascii_lowercase()));
+    }
+}
+
+bitflags! {
+    /// Why an object is hidden. Every system that hides objects owns one flag, so it can't show
+    /// an object another system hid.
+    #[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct HiddenBy: u8 {
+        /// Outside the hours of a timed object
+        const TIME = 1 << 0;
+        /// Far away while the camera is in a cull zone
+        const CULL_ZONE = 1 << 1;
+        /// Entirely behind an occluder
+        const OCCLUDER = 1 << 2;
+    }
+}
+
+// Sets or clears a flag, only marking the component as changed when it actually changes
+pub fn set_hidden(hidden: &mut Mut<HiddenBy>, flag: HiddenBy, value: bool) {
+    if hidden.contains(flag) != value {
+        hidden.set(flag, value);
+    }
+}
+
+pub fn apply_hidden(mut objects: Query<(&HiddenBy, &mut Visibility), Changed<HiddenBy>>) {
+    for (hidden, mut visibility) in &mut objects {
+        visibility.set_if_neq(if hidden.is_empty() {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
+
+/// Entity whose model is still loading, its meshes get spawned as children once it is loaded
+#[derive(Component)]
+pub struct PendingModel(pub Handle<Model>);
+
+pub fn instantiate_models(
+    query: Query<(Entity, &PendingModel)>,
+    mut models: ResMut<Assets<Model>>,
+    txds: Res<Assets<Txd>>,
+    mut materials: ResMut<Assets<GTAMaterial>>,
+    server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    for (entity, pending) in &query {
+        let Some(model) = models.get(&pending.0) else {
+            if server.load_state(&pending.0).is_failed() {
+                error!("Error loading model {:?}", pending.0.path());
+                commands.entity(entity).remove::<PendingModel>();
+            }
+            continue;
+        };
+
+        if !model.textures.is_empty() {
+            // Textures can only be looked up once every TXD in the chain is done loading
+            if model
+                .txds
+                .iter()
+                .any(|txd| txds.get(txd).is_none() && !server.load_state(txd).is_failed())
+            {
+                continue;
+            }
+            let model = models.get_mut(&pending.0).unwrap();
+            for (material, name) in std::mem::take(&mut model.textures) {
+                let texture = model
+                    .txds
+                    .iter()
+                    .filter_map(|txd| txds.get(txd))
+                    .find_map(|txd| txd.get(&name));
+                match (texture, materials.get_mut(&material)) {
+                    (Some(texture), Some(material)) => {
+                        material.texture = Some(texture.image.clone());
+                        // Cutout is only needed when the texture or the material can be transparent
+                        if material.alpha_mode == AlphaMode::AlphaToCoverage
+                            && !texture.has_alpha
+                            && material.color.alpha >= 1.0
+                        {
+                            material.alpha_mode = AlphaMode::Opaque;
+                        }
+                    }
+                    (None, _) => warn!("Texture {} not found for {:?}", name, pending.0.path()),
+                    _ => {}
+                }
+            }
         }
-    });
+        let model = models.get(&pending.0).unwrap();
+
+        let mut ent = commands.entity(entity);
+        ent.remove::<PendingModel>();
+
+        let Some(meshes) = model.geometries.last().filter(|g| !g.is_empty()) else {
+            warn!("{:?} contained zero meshes", pending.0.path());
+            continue;
+        };
+        ent.with_children(|parent| {
+            for (mesh, material) in meshes {
+                parent.spawn((Mesh3d(mesh.clone()), MeshMaterial3d(material.clone())));
+            }
+        });
+    }
+}
+
+/// Colliders of a single model, built once when its COL file is loaded.
+/// Cloning a collider only clones a reference to its shape, so instances share them.
+pub struct ModelCollision {
+    pub colliders: Vec<(Collider, Transform)>,
+}
+
+impl ModelCollision {
+    pub fn new(col: &CollV1) -> Self {
+        let mut colliders = Vec::new();
+        for sphere in &col.spheres {
+            colliders.push((
+                Collider::sphere(sphere.radius),
+                Transform::from_xyz(-sphere.center.x, sphere.center.z, sphere.center.y),
+            ));
+        }
+        for tbox in &col.boxes {
+            colliders.push((
+                Collider::cuboid(
+                    (tbox.max.x - tbox.min.x).abs(),
+                    (tbox.max.z - tbox.min.z).abs(),
+                    (tbox.max.y - tbox.min.y).abs(),
+                ),
+                Transform::from_xyz(
+                    -((tbox.max.x + tbox.min.x) / 2.0),
+                    (tbox.max.z + tbox.min.z) / 2.0,
+                    (tbox.max.y + tbox.min.y) / 2.0,
+                ),
+            ));
+        }
+        if !&col.vertices.is_empty() {
+            colliders.push((
+                Collider::trimesh(
+                    col.vertices
+                        .iter()
+                        .map(|v| Vec3 {
+                            x: -v.0[0],
+                            y: v.0[2],
+                            z: v.0[1],
+                        })
+                        .collect(),
+                    col.faces.iter().map(|f| [f.a, f.b, f.c]).collect(),
+                ),
+                Transform::IDENTITY,
+            ));
+        }
+        Self { colliders }
+    }
+}
+
+/// Entity waiting for the COL files to load, holds the lowercase model name
+#[derive(Component)]
+pub struct PendingCollision(pub String);
 
-    if let Some(col) = game_data.col.get(&data.name) {
-        spawn_collision(col, ent.id(), commands);
+pub fn instantiate_collision(
+    query: Query<(Entity, &PendingCollision)>,
+    game_data: Res<GameData>,
+    archives: Res<Assets<CollisionArchive>>,
+    server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    'entities: for (entity, pending) in &query {
+        // Later COL files override earlier ones, so we can only decide once those are loaded
+        for handle in game_data.col.iter().rev() {
+            match archives.get(handle) {
+                Some(archive) => {
+                    if let Some(collision) = archive.models.get(&pending.0) {
+                        commands.entity(entity).remove::<PendingCollision>();
+                        spawn_collision(collision, entity, &mut commands);
+                        continue 'entities;
+                    }
+                }
+                None if server.load_state(handle).is_failed() => {}
+                None => continue 'entities,
+            }
+        }
+        commands.entity(entity).remove::<PendingCollision>();
     }
 }
 
-pub fn spawn_collision(col: &CollV1, parent: Entity, mut commands: Commands) {
+pub fn spawn_collision(collision: &ModelCollision, parent: Entity, commands: &mut Commands) {
     let mut parent = commands.get_entity(parent).unwrap();
     parent.insert_if_new(RigidBody::Static);
 
-    for sphere in &col.spheres {
-        parent.with_child((
-            Collider::sphere(sphere.radius),
-            Transform::from_xyz(-sphere.center.x, sphere.center.z, sphere.center.y),
-        ));
-    }
-    for tbox in &col.boxes {
-        parent.with_child((
-            Collider::cuboid(
-                (tbox.max.x - tbox.min.x).abs(),
-                (tbox.max.z - tbox.min.z).abs(),
-                (tbox.max.y - tbox.min.y).abs(),
-            ),
-            Transform::from_xyz(
-                -((tbox.max.x + tbox.min.x) / 2.0),
-                (tbox.max.z + tbox.min.z) / 2.0,
-                (tbox.max.y + tbox.min.y) / 2.0,
-            ),
-        ));
-    }
-    if !&col.vertices.is_empty() {
-        parent.with_child(Collider::trimesh(
-            col.vertices
-                .iter()
-                .map(|v| Vec3 {
-                    x: -v.0[0],
-                    y: v.0[2],
-                    z: v.0[1],
-                })
-                .collect(),
-            col.faces.iter().map(|f| [f.a, f.b, f.c]).collect(),
-        ));
+    for (collider, transform) in &collision.colliders {
+        parent.with_child((collider.clone(), *transform));
     }
 }
 
@@ -174,3 +339,52 @@ impl ObjHandles {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::def::{parse_def, DefKind, InstLayout};
+
+    #[test]
+    fn instances_of_broken_ide_lines_are_skipped() {
+        let text = "objs\n1, bar, bartex, far, 0\n2, foo, footex, 100, 0\nend\n";
+        let def = parse_def(DefKind::Ide, InstLayout::Gta3, "test.ide", text);
+        assert_eq!(def.errors.len(), 1);
+        let mut game_data = GameData::default();
+        for (_, record) in def.records {
+            assert_eq!(game_data.ide.add(record), None);
+        }
+        assert!(game_data.ide.get_by_id(2).is_some());
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                watch_for_changes_override: Some(false),
+                ..default()
+            },
+        ))
+        .init_asset::<Mesh>()
+        .init_asset::<GTAMaterial>()
+        .init_resource::<EffectAssets>()
+        .init_resource::<PathGraph>()
+        .insert_resource(game_data)
+        .add_observer(spawn_obj);
+
+        // Placed by id and by name, both refer to the broken line
+        for (id, name) in [(1, "bar"), (0, "bar")] {
+            app.world_mut().trigger(SpawnObject {
+                id,
+                name: name.to_owned(),
+                pos: [0.0; 3],
+                scale: [1.0; 3],
+                rot: Quat::IDENTITY,
+                handle: None,
+            });
+        }
+        app.update();
+
+        let world = app.world_mut();
+        assert_eq!(world.query::<&HiddenBy>().iter(world).count(), 0);
+    }
+}
@@ -0,0 +1,251 @@
+use bevy::{
+    camera::{primitives::Aabb, visibility::VisibilitySystems},
+    prelude::*,
+};
+
+use crate::{
+    def::IplOccluder,
+    objects::{apply_hidden, set_hidden, HiddenBy, PendingModel},
+    to_xzy,
+};
+
+/// An occluder box in world space
+#[derive(Clone, Debug)]
+pub struct Occluder {
+    pub center: Vec3,
+    pub rotation: Quat,
+    pub half_size: Vec3,
+}
+
+impl Occluder {
+    fn to_local(&self, pos: Vec3) -> Vec3 {
+        self.rotation.inverse() * (pos - self.center)
+    }
+
+    pub fn contains(&self, pos: Vec3) -> bool {
+        self.to_local(pos).abs().cmple(self.half_size).all()
+    }
+
+    // Whether the line from a to b passes through the box, using the slab test in box space
+    pub fn blocks(&self, a: Vec3, b: Vec3) -> bool {
+        let start = self.to_local(a);
+        let dir = self.to_local(b) - start;
+        let (mut t_min, mut t_max) = (0.0f32, 1.0f32);
+        for axis in 0..3 {
+            let (s, d, h) = (start[axis], dir[axis], self.half_size[axis]);
+            if d.abs() < f32::EPSILON {
+                if s.abs() > h {
+                    return false;
+                }
+                continue;
+            }
+            let (t1, t2) = ((-h - s) / d, (h - s) / d);
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+            if t_min > t_max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Occluders from the occl sections of the IPL files
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Occluders {
+    #[reflect(ignore)]
+    occluders: Vec<Occluder>,
+    pub enabled: bool,
+    /// Occluders further than this from the camera are ignored
+    pub max_distance: f32,
+}
+
+impl Default for Occluders {
+    fn default() -> Self {
+        Self {
+            occluders: Vec::new(),
+            enabled: true,
+            max_distance: 300.0,
+        }
+    }
+}
+
+impl Occluders {
+    pub fn add(&mut self, occluder: IplOccluder) {
+        let [x, y, z] = occluder.pos;
+        let half_size = Vec3::new(occluder.width, occluder.height, occluder.length) / 2.0;
+        self.occluders.push(Occluder {
+            center: Vec3::from(to_xzy([x, y, z + occluder.height / 2.0])),
+            rotation: Quat::from_rotation_y(occluder.angle.to_radians()),
+            half_size,
+        });
+    }
+
+    pub fn occluders(&self) -> &[Occluder] {
+        &self.occluders
+    }
+}
+
+/// Instances hidden by occluders in the last frame
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct OcclusionStats {
+    pub culled: usize,
+}
+
+/// Box around the meshes of an object, relative to the object
+#[derive(Component)]
+pub struct ObjectBounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl ObjectBounds {
+    fn corners(&self, transform: &GlobalTransform) -> [Vec3; 8] {
+        std::array::from_fn(|i| {
+            let pick = |bit: usize, axis: usize| {
+                if i & (1 << bit) == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+            transform.transform_point(Vec3::new(pick(0, 0), pick(1, 1), pick(2, 2)))
+        })
+    }
+}
+
+// Bounds are computed once the meshes of an object are spawned and bevy calculated their boxes
+fn compute_object_bounds(
+    objects: Query<
+        (Entity, &Children),
+        (With<HiddenBy>, Without<ObjectBounds>, Without<PendingModel>),
+    >,
+    meshes: Query<(Option<&Aabb>, &Transform), With<Mesh3d>>,
+    mut commands: Commands,
+) {
+    'objects: for (entity, children) in &objects {
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        for (aabb, transform) in meshes.iter_many(children) {
+            let Some(aabb) = aabb else {
+                continue 'objects;
+            };
+            // Coronas turn towards the camera, so use a box that fits any rotation
+            let center = transform.transform_point(aabb.center.into());
+            let radius = (Vec3::from(aabb.half_extents) * transform.scale).length();
+            let (min, max) = (center - radius, center + radius);
+            bounds = Some(match bounds {
+                Some((a, b)) => (a.min(min), b.max(max)),
+                None => (min, max),
+            });
+        }
+        if let Some((min, max)) = bounds {
+            commands.entity(entity).insert(ObjectBounds { min, max });
+        }
+    }
+}
+
+// Hides objects that are entirely behind an occluder. The part of the world an occluder hides
+// from the camera is convex, so an object is hidden when every corner of its box is.
+fn occlude_objects(
+    occluders: Res<Occluders>,
+    mut stats: ResMut<OcclusionStats>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+    mut objects: Query<(&GlobalTransform, &ObjectBounds, &mut HiddenBy)>,
+) {
+    let Ok(camera) = camera.single() else {
+        return;
+    };
+    let camera = camera.translation();
+    let active: Vec<&Occluder> = if occluders.enabled {
+        occluders
+            .occluders
+            .iter()
+            .filter(|o| o.center.distance(camera) <= occluders.max_distance)
+            .filter(|o| !o.contains(camera))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut culled = 0;
+    for (transform, bounds, mut hidden) in &mut objects {
+        let corners = bounds.corners(transform);
+        let occluded = active
+            .iter()
+            .any(|o| corners.iter().all(|&corner| o.blocks(camera, corner)));
+        set_hidden(&mut hidden, HiddenBy::OCCLUDER, occluded);
+        culled += occluded as usize;
+    }
+    stats.culled = culled;
+}
+
+/// Debug overlay that draws the occluders
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct OccluderOverlay {
+    pub enabled: bool,
+    pub toggle: KeyCode,
+}
+
+impl Default for OccluderOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            toggle: KeyCode::F6,
+        }
+    }
+}
+
+fn toggle_occluder_overlay(keys: Res<ButtonInput<KeyCode>>, mut overlay: ResMut<OccluderOverlay>) {
+    if keys.just_pressed(overlay.toggle) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+fn draw_occluder_overlay(
+    overlay: Res<OccluderOverlay>,
+    occluders: Res<Occluders>,
+    mut gizmos: Gizmos,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    for occluder in &occluders.occluders {
+        gizmos.cuboid(
+            Transform {
+                translation: occluder.center,
+                rotation: occluder.rotation,
+                scale: occluder.half_size * 2.0,
+            },
+            Color::srgb(1.0, 0.2, 0.8),
+        );
+    }
+}
+
+pub struct OcclusionPlugin;
+
+impl Plugin for OcclusionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Occluders>()
+            .init_resource::<OcclusionStats>()
+            .init_resource::<OccluderOverlay>()
+            .register_type::<Occluders>()
+            .register_type::<OcclusionStats>()
+            .register_type::<OccluderOverlay>()
+            .add_systems(
+                Update,
+                (
+                    compute_object_bounds,
+                    (toggle_occluder_overlay, draw_occluder_overlay).chain(),
+                ),
+            )
+            .add_systems(
+                PostUpdate,
+                occlude_objects
+                    .before(apply_hidden)
+                    .before(VisibilitySystems::VisibilityPropagate),
+            );
+    }
+}
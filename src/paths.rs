@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    def::{PathGroup, PathNodeType, PathType},
+    to_xzy,
+};
+
+/// External nodes of neighbouring models closer than this are the same point
+const STITCH_DISTANCE: f32 = 1.0;
+
+/// A node of the path graph in world space
+#[derive(Clone, Debug)]
+pub struct GraphNode {
+    pub pos: Vec3,
+    pub path_type: PathType,
+    /// Whether the node connects to the paths of other models, never PathNodeType::None
+    pub node_type: PathNodeType,
+    pub cross_road: bool,
+    pub median: f32,
+    pub left_lanes: u8,
+    pub right_lanes: u8,
+    /// Indices of the nodes this one is connected to, links go both ways
+    pub links: Vec<usize>,
+}
+
+/// Ped and car paths of every placed object and of the IPL files, stitched together at their
+/// external nodes
+#[derive(Resource, Default)]
+pub struct PathGraph {
+    nodes: Vec<GraphNode>,
+    /// External nodes by grid cell, cells are STITCH_DISTANCE wide
+    external: HashMap<(PathType, IVec3), Vec<usize>>,
+}
+
+impl PathGraph {
+    pub fn nodes(&self) -> &[GraphNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, index: usize) -> Option<&GraphNode> {
+        self.nodes.get(index)
+    }
+
+    // The closest node of a path type, for finding where a ped or car joins the graph
+    pub fn nearest(&self, pos: Vec3, path_type: PathType) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.path_type == path_type)
+            .min_by(|(_, a), (_, b)| {
+                a.pos
+                    .distance_squared(pos)
+                    .total_cmp(&b.pos.distance_squared(pos))
+            })
+            .map(|(i, _)| i)
+    }
+
+    // Adds the path group of a model placed with transform. External nodes that meet an external
+    // node already in the graph are merged with it, which connects the paths of both models.
+    pub fn add_group(&mut self, group: &PathGroup, transform: &Transform) {
+        let mut indices = Vec::with_capacity(group.nodes.len());
+        for node in &group.nodes {
+            if node.node_type == PathNodeType::None {
+                indices.push(None);
+                continue;
+            }
+            let pos = transform.transform_point(Vec3::from(to_xzy(node.pos)));
+            let merged = match node.node_type {
+                PathNodeType::External => self.find_external(group.path_type, pos),
+                _ => None,
+            };
+            let index = merged.unwrap_or_else(|| {
+                self.nodes.push(GraphNode {
+                    pos,
+                    path_type: group.path_type,
+                    node_type: node.node_type,
+                    cross_road: node.cross_road,
+                    median: node.median,
+                    left_lanes: node.left_lanes,
+                    right_lanes: node.right_lanes,
+                    links: Vec::new(),
+                });
+                let index = self.nodes.len() - 1;
+                if node.node_type == PathNodeType::External {
+                    self.external
+                        .entry((group.path_type, cell(pos)))
+                        .or_default()
+                        .push(index);
+                }
+                index
+            });
+            indices.push(Some(index));
+        }
+
+        for (i, node) in group.nodes.iter().enumerate() {
+            let next = usize::try_from(node.next)
+                .ok()
+                .and_then(|next| indices.get(next).copied().flatten());
+            if let (Some(a), Some(b)) = (indices[i], next) {
+                self.link(a, b);
+            }
+        }
+    }
+
+    fn find_external(&self, path_type: PathType, pos: Vec3) -> Option<usize> {
+        let center = cell(pos);
+        let mut nearest = None;
+        let mut nearest_distance = STITCH_DISTANCE * STITCH_DISTANCE;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let key = (path_type, center + IVec3::new(x, y, z));
+                    for &index in self.external.get(&key).into_iter().flatten() {
+                        let distance = self.nodes[index].pos.distance_squared(pos);
+                        if distance < nearest_distance {
+                            nearest = Some(index);
+                            nearest_distance = distance;
+                        }
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    fn link(&mut self, a: usize, b: usize) {
+        if a == b || self.nodes[a].links.contains(&b) {
+            return;
+        }
+        self.nodes[a].links.push(b);
+        self.nodes[b].links.push(a);
+    }
+}
+
+fn cell(pos: Vec3) -> IVec3 {
+    (pos / STITCH_DISTANCE).floor().as_ivec3()
+}
+
+/// Debug overlay that draws the path graph
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct PathOverlay {
+    pub enabled: bool,
+    pub toggle: KeyCode,
+}
+
+impl Default for PathOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            toggle: KeyCode::F5,
+        }
+    }
+}
+
+fn toggle_path_overlay(keys: Res<ButtonInput<KeyCode>>, mut overlay: ResMut<PathOverlay>) {
+    if keys.just_pressed(overlay.toggle) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+// Ped paths are drawn green and car paths orange, external nodes get a sphere
+fn draw_path_overlay(overlay: Res<PathOverlay>, graph: Res<PathGraph>, mut gizmos: Gizmos) {
+    if !overlay.enabled {
+        return;
+    }
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let color = match node.path_type {
+            PathType::Ped => Color::srgb(0.2, 1.0, 0.2),
+            PathType::Car => Color::srgb(1.0, 0.6, 0.1),
+        };
+        // Every link is stored on both nodes, draw it once
+        for &link in node.links.iter().filter(|&&link| link > i) {
+            gizmos.line(node.pos, graph.nodes[link].pos, color);
+        }
+        if node.node_type == PathNodeType::External {
+            gizmos.sphere(node.pos, 0.3, color);
+        }
+    }
+}
+
+pub struct PathsPlugin;
+
+impl Plugin for PathsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PathGraph>()
+            .init_resource::<PathOverlay>()
+            .register_type::<PathOverlay>()
+            .add_systems(Update, (toggle_path_overlay, draw_path_overlay).chain());
+    }
+}
@@ -0,0 +1,356 @@
+use bevy::{
+    image::{ImageAddressMode, ImageFilterMode},
+    render::render_resource::TextureFormat,
+};
+use rw_rs::bsf::tex::{RasterFormat, TextureAddressingMode, TextureFilteringMode};
+
+/// Block compression used by a D3D raster
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Dxt1,
+    Dxt3,
+}
+
+impl Compression {
+    // D3D8 rasters store the DXT variant they use in their compression field, 0 means uncompressed.
+    // GTA only uses DXT1 and DXT3.
+    pub fn from_d3d8(compression: u8) -> Option<Self> {
+        match compression {
+            1 => Some(Compression::Dxt1),
+            3 => Some(Compression::Dxt3),
+            _ => None,
+        }
+    }
+
+    pub fn block_size(self) -> usize {
+        match self {
+            Compression::Dxt1 => 8,
+            Compression::Dxt3 => 16,
+        }
+    }
+
+    pub fn level_size(self, width: usize, height: usize) -> usize {
+        width.div_ceil(4) * height.div_ceil(4) * self.block_size()
+    }
+
+    pub fn texture_format(self) -> TextureFormat {
+        match self {
+            Compression::Dxt1 => TextureFormat::Bc1RgbaUnormSrgb,
+            Compression::Dxt3 => TextureFormat::Bc2RgbaUnormSrgb,
+        }
+    }
+
+    // Decompresses the blocks into RGBA8, for devices without BC texture support
+    pub fn decode(self, data: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let mut out = vec![0; width * height * 4];
+        let blocks_x = width.div_ceil(4);
+        for (i, block) in data.chunks_exact(self.block_size()).enumerate() {
+            let (bx, by) = ((i % blocks_x) * 4, (i / blocks_x) * 4);
+            if by >= height {
+                break;
+            }
+            let texels = match self {
+                Compression::Dxt1 => decode_color_block(block, true),
+                Compression::Dxt3 => {
+                    let mut texels = decode_color_block(&block[8..], false);
+                    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+                    for (t, texel) in texels.iter_mut().enumerate() {
+                        let a = ((alpha >> (t * 4)) & 0xF) as u8;
+                        texel[3] = (a << 4) | a;
+                    }
+                    texels
+                }
+            };
+            for (t, texel) in texels.iter().enumerate() {
+                let (x, y) = (bx + t % 4, by + t / 4);
+                if x < width && y < height {
+                    let offset = (y * width + x) * 4;
+                    out[offset..offset + 4].copy_from_slice(texel);
+                }
+            }
+        }
+        out
+    }
+}
+
+// Splits the raster data following the palette into its mip levels, each level is prefixed with
+// its size. Returns None when the data ends in the middle of a level.
+pub fn split_levels(mut data: &[u8], max_levels: usize) -> Option<Vec<&[u8]>> {
+    let mut levels = Vec::new();
+    while levels.len() < max_levels && !data.is_empty() {
+        let size = u32::from_le_bytes(data.get(..4)?.try_into().unwrap()) as usize;
+        levels.push(data.get(4..4 + size)?);
+        data = &data[4 + size..];
+    }
+    Some(levels)
+}
+
+pub fn bytes_per_pixel(format: &RasterFormat) -> Option<usize> {
+    match format {
+        RasterFormat::Format8888 | RasterFormat::Format888 => Some(4),
+        RasterFormat::Format1555
+        | RasterFormat::Format555
+        | RasterFormat::Format565
+        | RasterFormat::Format4444 => Some(2),
+        RasterFormat::FormatLum8 => Some(1),
+        _ => None,
+    }
+}
+
+// Converts a level of an uncompressed, non-paletted raster to RGBA8.
+// The D3D formats are little endian with the blue channel in the lowest bits.
+pub fn decode_pixels(
+    format: &RasterFormat,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Option<Vec<u8>> {
+    let pixels = width * height;
+    let words = || {
+        data.chunks_exact(2)
+            .take(pixels)
+            .map(|p| u16::from_le_bytes([p[0], p[1]]))
+    };
+    let out: Vec<u8> = match format {
+        RasterFormat::Format8888 => data
+            .chunks_exact(4)
+            .take(pixels)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect(),
+        RasterFormat::Format888 => data
+            .chunks_exact(4)
+            .take(pixels)
+            .flat_map(|p| [p[2], p[1], p[0], 255])
+            .collect(),
+        RasterFormat::Format1555 => words()
+            .flat_map(|p| {
+                let [r, g, b] = expand555(p);
+                [r, g, b, if p >> 15 != 0 { 255 } else { 0 }]
+            })
+            .collect(),
+        RasterFormat::Format555 => words()
+            .flat_map(|p| {
+                let [r, g, b] = expand555(p);
+                [r, g, b, 255]
+            })
+            .collect(),
+        RasterFormat::Format565 => words().flat_map(rgb565_to_rgba8).collect(),
+        RasterFormat::Format4444 => words()
+            .flat_map(|p| {
+                let [a, r, g, b] = [p >> 12, p >> 8, p >> 4, p].map(|c| (c & 0xF) as u8);
+                [r, g, b, a].map(|c| (c << 4) | c)
+            })
+            .collect(),
+        RasterFormat::FormatLum8 => data
+            .iter()
+            .take(pixels)
+            .flat_map(|&l| [l, l, l, 255])
+            .collect(),
+        _ => return None,
+    };
+    (out.len() == pixels * 4).then_some(out)
+}
+
+// Converts a level of a paletted raster to RGBA8, there is one index byte per pixel
+pub fn decode_paletted(
+    indices: &[u8],
+    palette: &[[u8; 4]],
+    width: usize,
+    height: usize,
+) -> Option<Vec<u8>> {
+    indices
+        .get(..width * height)?
+        .iter()
+        .map(|&i| palette.get(i as usize).copied())
+        .collect::<Option<Vec<_>>>()
+        .map(|colors| colors.concat())
+}
+
+// Box filters an RGBA8 image down to 1x1, for rasters that want mipmaps but don't include them.
+// Returns the concatenated levels and the number of levels.
+pub fn generate_mips(base: Vec<u8>, width: usize, height: usize) -> (Vec<u8>, u32) {
+    if width == 0 || height == 0 {
+        return (base, 1);
+    }
+    let mut data = base;
+    let mut level_start = 0;
+    let (mut w, mut h) = (width, height);
+    let mut levels = 1;
+    while w > 1 || h > 1 {
+        let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+        let mut next = Vec::with_capacity(nw * nh * 4);
+        for y in 0..nh {
+            for x in 0..nw {
+                let samples = [
+                    (x * 2, y * 2),
+                    ((x * 2 + 1).min(w - 1), y * 2),
+                    (x * 2, (y * 2 + 1).min(h - 1)),
+                    ((x * 2 + 1).min(w - 1), (y * 2 + 1).min(h - 1)),
+                ];
+                for c in 0..4 {
+                    let sum: u32 = samples
+                        .iter()
+                        .map(|&(sx, sy)| data[level_start + (sy * w + sx) * 4 + c] as u32)
+                        .sum();
+                    next.push((sum / 4) as u8);
+                }
+            }
+        }
+        level_start = data.len();
+        data.extend(next);
+        (w, h) = (nw, nh);
+        levels += 1;
+    }
+    (data, levels)
+}
+
+fn expand555(p: u16) -> [u8; 3] {
+    [p >> 10, p >> 5, p].map(|c| {
+        let c = (c & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    })
+}
+
+pub fn rgb565_to_rgba8(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 0x1F) as u8;
+    let g = ((color >> 5) & 0x3F) as u8;
+    let b = (color & 0x1F) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+        255,
+    ]
+}
+
+// Decodes the 16 texels of a DXT color block. DXT1 blocks where the first color is not larger than
+// the second use three colors and transparent black instead of four colors.
+fn decode_color_block(block: &[u8], dxt1: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let (p0, p1) = (rgb565_to_rgba8(c0), rgb565_to_rgba8(c1));
+    let mix = |w0: u16, w1: u16| -> [u8; 4] {
+        std::array::from_fn(|i| match i {
+            3 => 255,
+            i => ((p0[i] as u16 * w0 + p1[i] as u16 * w1) / (w0 + w1)) as u8,
+        })
+    };
+    let palette = if c0 > c1 || !dxt1 {
+        [p0, p1, mix(2, 1), mix(1, 2)]
+    } else {
+        [p0, p1, mix(1, 1), [0; 4]]
+    };
+
+    let mut texels = [[0; 4]; 16];
+    for (t, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (t * 2)) & 0b11) as usize];
+    }
+    texels
+}
+
+pub fn address_mode(mode: &TextureAddressingMode) -> ImageAddressMode {
+    match mode {
+        TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS => ImageAddressMode::Repeat,
+        TextureAddressingMode::TEXTUREADDRESSWRAP => ImageAddressMode::Repeat,
+        TextureAddressingMode::TEXTUREADDRESSMIRROR => ImageAddressMode::MirrorRepeat,
+        TextureAddressingMode::TEXTUREADDRESSCLAMP => ImageAddressMode::ClampToEdge,
+        TextureAddressingMode::TEXTUREADDRESSBORDER => ImageAddressMode::ClampToBorder,
+    }
+}
+
+// Returns the texel filter and the filter between mip levels
+pub fn filter_mode(mode: &TextureFilteringMode) -> (ImageFilterMode, ImageFilterMode) {
+    use ImageFilterMode::{Linear, Nearest};
+    match mode {
+        TextureFilteringMode::FILTERNAFILTERMODE => (Linear, Linear),
+        TextureFilteringMode::FILTERNEAREST => (Nearest, Nearest),
+        TextureFilteringMode::FILTERLINEAR => (Linear, Nearest),
+        TextureFilteringMode::FILTERMIPNEAREST => (Nearest, Nearest),
+        TextureFilteringMode::FILTERMIPLINEAR => (Nearest, Linear),
+        TextureFilteringMode::FILTERLINEARMIPNEAREST => (Linear, Nearest),
+        TextureFilteringMode::FILTERLINEARMIPLINEAR => (Linear, Linear),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0xF800;
+    const BLUE: u16 = 0x001F;
+    // Texels 0 to 3 use palette entries 0 to 3, the rest use entry 0
+    const INDICES: u32 = 0b11_10_01_00;
+
+    fn color_block(c0: u16, c1: u16) -> Vec<u8> {
+        [c0.to_le_bytes(), c1.to_le_bytes()]
+            .concat()
+            .into_iter()
+            .chain(INDICES.to_le_bytes())
+            .collect()
+    }
+
+    fn texel(rgba: &[u8], i: usize) -> [u8; 4] {
+        rgba[i * 4..i * 4 + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn dxt1_four_colors() {
+        let rgba = Compression::Dxt1.decode(&color_block(RED, BLUE), 4, 4);
+        assert_eq!(rgba.len(), 4 * 4 * 4);
+        assert_eq!(texel(&rgba, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&rgba, 1), [0, 0, 255, 255]);
+        assert_eq!(texel(&rgba, 2), [170, 0, 85, 255]);
+        assert_eq!(texel(&rgba, 3), [85, 0, 170, 255]);
+        assert_eq!(texel(&rgba, 15), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn dxt1_one_bit_alpha() {
+        // c0 <= c1 switches to three colors and transparent black
+        let rgba = Compression::Dxt1.decode(&color_block(BLUE, RED), 4, 4);
+        assert_eq!(texel(&rgba, 0), [0, 0, 255, 255]);
+        assert_eq!(texel(&rgba, 1), [255, 0, 0, 255]);
+        assert_eq!(texel(&rgba, 2), [127, 0, 127, 255]);
+        assert_eq!(texel(&rgba, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dxt1_equal_colors_are_three_color_mode() {
+        let rgba = Compression::Dxt1.decode(&color_block(RED, RED), 4, 4);
+        assert_eq!(texel(&rgba, 2), [255, 0, 0, 255]);
+        assert_eq!(texel(&rgba, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dxt3_explicit_alpha() {
+        // 4 bits of alpha per texel: 0xF, 0x0, 0x8, then 0 for the rest
+        let mut block = vec![0x0F, 0x08, 0, 0, 0, 0, 0, 0];
+        // DXT3 always uses four colors, even when c0 <= c1
+        block.extend(color_block(BLUE, RED));
+        let rgba = Compression::Dxt3.decode(&block, 4, 4);
+        assert_eq!(texel(&rgba, 0), [0, 0, 255, 255]);
+        assert_eq!(texel(&rgba, 1), [255, 0, 0, 0]);
+        assert_eq!(texel(&rgba, 2), [85, 0, 170, 0x88]);
+        assert_eq!(texel(&rgba, 3), [170, 0, 85, 0]);
+    }
+
+    #[test]
+    fn dxt_smaller_than_a_block() {
+        let rgba = Compression::Dxt1.decode(&color_block(RED, BLUE), 2, 2);
+        assert_eq!(rgba.len(), 2 * 2 * 4);
+        // Row 1 starts with texel 4 of the block
+        assert_eq!(texel(&rgba, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(&rgba, 1), [0, 0, 255, 255]);
+        assert_eq!(texel(&rgba, 2), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn d3d8_compression_field() {
+        assert_eq!(Compression::from_d3d8(0), None);
+        assert_eq!(Compression::from_d3d8(1), Some(Compression::Dxt1));
+        assert_eq!(Compression::from_d3d8(3), Some(Compression::Dxt3));
+        assert_eq!(Compression::from_d3d8(5), None);
+    }
+}
@@ -2,13 +2,14 @@ use binrw::{BinReaderExt, BinWriterExt};
 use std::{
     ffi::CStr,
     io::{Cursor, Seek},
+    path::Path,
 };
 
 use bevy::prelude::*;
 
 use crate::{
+    install::GameInstall,
     objects::{ObjHandles, SpawnObject},
-    GTA_DIR,
 };
 
 #[derive(Resource)]
@@ -54,31 +55,28 @@ macro_rules! scm_matches {
 }
 
 impl ScriptEngine {
-    fn new(scm: Vec<u8>) -> Self {
+    fn new(scm: Vec<u8>) -> Result<Self> {
         let mut scm = Cursor::new(scm);
-        scm.seek(std::io::SeekFrom::Start(3)).unwrap();
-        let object_offset = scm.read_le::<u32>().unwrap() + 3 + 4 + 1;
-        scm.seek(std::io::SeekFrom::Start(object_offset as u64))
-            .unwrap();
-        let num_objects = scm.read_le::<u32>().unwrap();
+        scm.seek(std::io::SeekFrom::Start(3))?;
+        let object_offset = scm.read_le::<u32>()? + 3 + 4 + 1;
+        scm.seek(std::io::SeekFrom::Start(object_offset as u64))?;
+        let num_objects = scm.read_le::<u32>()?;
         let mut obj_names = Vec::with_capacity(num_objects as usize);
         for _ in 0..num_objects {
             obj_names.push(
-                CStr::from_bytes_until_nul(&scm.read_le::<[u8; 24]>().unwrap())
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
+                CStr::from_bytes_until_nul(&scm.read_le::<[u8; 24]>()?)?
+                    .to_str()?
                     .to_owned(),
             );
         }
 
-        Self {
+        Ok(Self {
             scm,
             scripts: vec![Script::default()],
             current_script: 0,
             global_offset: 8,
             obj_names,
-        }
+        })
     }
 
     fn step(&mut self, mut commands: Commands, mut handles: ResMut<ObjHandles>) {
@@ -259,14 +257,25 @@ fn run_scriptengine(
     script_engine.step(commands, handles);
 }
 
+fn load_script(install: &GameInstall) -> Result<ScriptEngine> {
+    let path = install
+        .get_path(Path::new(install.script_file()))
+        .ok_or(format!("{} not found!", install.script_file()))?;
+    ScriptEngine::new(std::fs::read(path)?)
+}
+
 pub struct ScriptEnginePlugin;
 
 impl Plugin for ScriptEnginePlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ScriptEngine::new(
-            std::fs::read(GTA_DIR.join("data/main.scm")).unwrap(),
-        ))
-        .add_systems(FixedUpdate, run_scriptengine);
+        // Without a script the world still loads, it just stays empty of scripted objects
+        match load_script(app.world().resource::<GameInstall>()) {
+            Ok(engine) => {
+                app.insert_resource(engine)
+                    .add_systems(FixedUpdate, run_scriptengine);
+            }
+            Err(e) => error!("Not running the main script: {e}"),
+        }
     }
 }
 
@@ -1,35 +1,79 @@
 use std::{
-    fs,
     path::{Path, PathBuf},
+    sync::PoisonError,
 };
 
-use crate::GTA_DIR;
-
-// Case-insensitive path search from data_dir
-pub fn get_path(path: &Path) -> Option<PathBuf> {
-    let mut matched = GTA_DIR.to_owned();
-    for elem in path.components() {
-        let Ok(iter) = fs::read_dir(&matched) else {
-            return None;
-        };
-        let mut found = String::new();
-        for file in iter {
-            let Ok(file) = file else { continue };
-            let file_name = file.file_name();
-            let file_name = file_name.to_string_lossy();
-            if file_name.to_ascii_lowercase().as_str()
-                == elem.as_os_str().to_ascii_lowercase().as_os_str()
-            {
-                found = file_name.to_string();
-                break;
-            }
+use crate::{FILE_INDEX, MOD_DIRS};
+
+/// File types that can be replaced by putting them in a mod directory
+const MOD_EXTENSIONS: [&str; 6] = ["dff", "txd", "col", "ide", "ipl", "dat"];
+
+// Case-insensitive path search in the mod directories, later directories take priority.
+// Files are looked up by their full path first and by their file name second.
+pub fn get_mod_path(path: &Path) -> Option<PathBuf> {
+    if !path
+        .extension()
+        .is_some_and(|ext| MOD_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+    {
+        return None;
+    }
+    MOD_DIRS.get()?.iter().rev().find_map(|dir| {
+        get_path_in(dir, path).or_else(|| get_path_in(dir, Path::new(path.file_name()?)))
+    })
+}
+
+// Case-insensitive path search from root, using the cached file index
+pub fn get_path_in(root: &Path, path: &Path) -> Option<PathBuf> {
+    let resolve = |p: &Path| {
+        if p.as_os_str().is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(p)
         }
-        if found.is_empty() {
-            return None;
+    };
+    if let Some(index) = FILE_INDEX
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(root)
+    {
+        return index.get(path).map(resolve);
+    }
+    FILE_INDEX
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .build(root)
+        .get(path)
+        .map(resolve)
+}
+
+// Case-insensitive glob match supporting `*` and `?`, used to select archive entries
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it currently matches up to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp + 1;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
         }
-        matched = matched.join(found);
     }
-    Some(matched)
+    pattern[p..].iter().all(|&c| c == b'*')
 }
 
 // We need this to deal with windows paths on non-windows platforms
@@ -41,3 +85,45 @@ pub fn to_path(input: &str) -> PathBuf {
 pub fn to_xzy<T: Copy + std::ops::Neg<Output = T>>(coords: [T; 3]) -> [T; 3] {
     [-coords[0], coords[2], coords[1]]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_star_matches_any_run() {
+        assert!(glob_match("*.txd", "generic.txd"));
+        assert!(glob_match("*.txd", ".txd"));
+        assert!(glob_match("a*b*c", "aXXbYYbZc"));
+        assert!(!glob_match("*.txd", "generic.dff"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character() {
+        assert!(glob_match("car?.dff", "car1.dff"));
+        assert!(!glob_match("car?.dff", "car.dff"));
+        assert!(!glob_match("car?.dff", "car12.dff"));
+    }
+
+    #[test]
+    fn glob_ignores_case() {
+        assert!(glob_match("*.TXD", "Generic.txd"));
+        assert!(glob_match("bar.dff", "BAR.DFF"));
+    }
+
+    #[test]
+    fn glob_empty_pattern_only_matches_empty_names() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "bar.dff"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn glob_trailing_star() {
+        assert!(glob_match("bar*", "bar"));
+        assert!(glob_match("bar*", "bar.dff"));
+        assert!(glob_match("bar**", "barrel.txd"));
+        assert!(!glob_match("bar*", "ba"));
+    }
+}
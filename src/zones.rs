@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+
+use crate::{
+    def::{IplZone, IslandLevel, ZoneType},
+    to_xzy,
+};
+
+/// A named zone box in world space
+#[derive(Clone, Debug)]
+pub struct Zone {
+    pub name: String,
+    pub zone_type: ZoneType,
+    pub level: IslandLevel,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Zone {
+    pub fn contains(&self, pos: Vec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmple(self.max).all()
+    }
+
+    fn volume(&self) -> f32 {
+        (self.max - self.min).element_product()
+    }
+}
+
+/// Every zone from the zone sections of the IPL and mapzone files
+#[derive(Resource, Default)]
+pub struct ZoneRegistry {
+    zones: Vec<Zone>,
+}
+
+impl ZoneRegistry {
+    pub fn add(&mut self, zone: IplZone) {
+        let [a, b] = zone.corners.map(|corner| Vec3::from(to_xzy(corner)));
+        self.zones.push(Zone {
+            name: zone.name,
+            zone_type: zone.zone_type,
+            level: zone.level,
+            min: a.min(b),
+            max: a.max(b),
+        });
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .find(|zone| zone.name.eq_ignore_ascii_case(name))
+    }
+
+    // The navigation zone a position is in, the one whose name the game shows
+    pub fn zone_at(&self, pos: Vec3) -> Option<&Zone> {
+        self.smallest_at(pos, |t| {
+            matches!(t, ZoneType::Default | ZoneType::Navigation)
+        })
+    }
+
+    pub fn info_zone_at(&self, pos: Vec3) -> Option<&Zone> {
+        self.smallest_at(pos, |t| t == ZoneType::Info)
+    }
+
+    pub fn map_zone_at(&self, pos: Vec3) -> Option<&Zone> {
+        self.smallest_at(pos, |t| t == ZoneType::Map)
+    }
+
+    // Zones are nested inside bigger ones, so the smallest zone containing a position is the
+    // innermost one
+    fn smallest_at(&self, pos: Vec3, filter: impl Fn(ZoneType) -> bool) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| filter(zone.zone_type) && zone.contains(pos))
+            .min_by(|a, b| a.volume().total_cmp(&b.volume()))
+    }
+}
+
+/// Entity whose zone is tracked, like the camera or the player
+#[derive(Component, Default)]
+pub struct ZoneTracker {
+    /// Name of the navigation zone the entity is in
+    pub zone: Option<String>,
+}
+
+/// Triggered when a tracked entity moves into a different navigation zone
+#[derive(Event)]
+pub struct ZoneEntered {
+    pub entity: Entity,
+    pub zone: String,
+    pub level: IslandLevel,
+}
+
+fn track_zones(
+    registry: Res<ZoneRegistry>,
+    mut trackers: Query<(Entity, &GlobalTransform, &mut ZoneTracker)>,
+    mut commands: Commands,
+) {
+    for (entity, transform, mut tracker) in &mut trackers {
+        let zone = registry.zone_at(transform.translation());
+        if tracker.zone.as_deref() == zone.map(|zone| zone.name.as_str()) {
+            continue;
+        }
+        tracker.zone = zone.map(|zone| zone.name.clone());
+        if let Some(zone) = zone {
+            commands.trigger(ZoneEntered {
+                entity,
+                zone: zone.name.clone(),
+                level: zone.level,
+            });
+        }
+    }
+}
+
+pub struct ZonesPlugin;
+
+impl Plugin for ZonesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ZoneRegistry>()
+            .add_systems(Update, track_zones);
+    }
+}